@@ -0,0 +1,70 @@
+use crate::runtime_error::RuntimeErrorKind;
+use crate::value::{NativeFunction, Value};
+use std::sync::OnceLock;
+
+/// Functions available in the global scope of every program
+pub const BUILTINS: &[NativeFunction] = &[
+    NativeFunction {
+        name: "len",
+        arity: 1,
+        func: len,
+    },
+    NativeFunction {
+        name: "argc",
+        arity: 0,
+        func: argc,
+    },
+    NativeFunction {
+        name: "argv",
+        arity: 1,
+        func: argv,
+    },
+];
+
+/// Command line arguments passed to the script, after the script name
+static SCRIPT_ARGS: OnceLock<Vec<String>> = OnceLock::new();
+
+/// Make `args` available to scripts through `argc()` and `argv(i)`.
+/// Only the first call has an effect.
+pub fn set_args(args: Vec<String>) {
+    let _ = SCRIPT_ARGS.set(args);
+}
+
+fn script_args() -> &'static [String] {
+    SCRIPT_ARGS.get().map_or(&[], Vec::as_slice)
+}
+
+/// Number of characters in a string
+fn len(args: &[Value]) -> Result<Value, RuntimeErrorKind> {
+    match &args[0] {
+        Value::Str(string) => Ok(Value::Int(string.chars().count() as i64)),
+        other => Err(RuntimeErrorKind::TypeMismatch {
+            expected: "string",
+            found: other.type_name(),
+        }),
+    }
+}
+
+/// Number of arguments passed to the script
+fn argc(_: &[Value]) -> Result<Value, RuntimeErrorKind> {
+    Ok(Value::Int(script_args().len() as i64))
+}
+
+/// Argument `i` passed to the script, as a string
+fn argv(args: &[Value]) -> Result<Value, RuntimeErrorKind> {
+    let Value::Int(index) = args[0] else {
+        return Err(RuntimeErrorKind::TypeMismatch {
+            expected: "int",
+            found: args[0].type_name(),
+        });
+    };
+
+    usize::try_from(index)
+        .ok()
+        .and_then(|i| script_args().get(i))
+        .map(|arg| Value::Str(arg.into()))
+        .ok_or(RuntimeErrorKind::IndexOutOfRange {
+            index,
+            len: script_args().len(),
+        })
+}
@@ -0,0 +1,162 @@
+use crate::span::Span;
+use ecow::EcoString;
+use std::fmt;
+use std::rc::Rc;
+
+macro_rules! opcodes {
+    ($($(#[$doc:meta])* $name:ident,)*) => {
+        /// Instruction of the bytecode VM, operands follow the opcode byte.
+        /// `u16` operands are big endian.
+        #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+        #[repr(u8)]
+        pub enum OpCode {
+            $($(#[$doc])* $name,)*
+        }
+
+        impl OpCode {
+            /// Every opcode, indexed by its byte
+            const ALL: &'static [OpCode] = &[$(OpCode::$name,)*];
+        }
+    };
+}
+
+opcodes! {
+    /// `u16` constant index, pushes the constant
+    Constant,
+    Nil,
+    True,
+    False,
+    Pop,
+    /// `u8` slot in the current frame
+    GetLocal,
+    /// `u8` slot, leaves the assigned value on the stack
+    SetLocal,
+    /// `u8` index in the closure's upvalues
+    GetUpvalue,
+    /// `u8` index, leaves the assigned value on the stack
+    SetUpvalue,
+    /// `u16` constant index of the name
+    GetGlobal,
+    /// `u16` constant index of the name, leaves the assigned value on the stack
+    SetGlobal,
+    /// `u16` constant index of the name, pops the value
+    DefineGlobal,
+    /// Like `DefineGlobal`, but the global can't be assigned to afterwards
+    DefineConstGlobal,
+    /// `u16` constant index of the name of a local constant, fails as it can't be assigned to
+    AssignConstant,
+    Equal,
+    NotEqual,
+    Less,
+    Greater,
+    LessEqual,
+    GreaterEqual,
+    Add,
+    Subtract,
+    Multiply,
+    Divide,
+    Negate,
+    Not,
+    /// Pops the index and the value
+    Index,
+    /// `u8` flags, bit 0 set when a start bound is on the stack and bit 1 for an end bound
+    Slice,
+    /// `u16` number of values to pop and join into one string
+    BuildString,
+    /// Fails unless the top of the stack is an int, used for `for` range bounds
+    CheckInt,
+    Print,
+    /// `u16` forward offset
+    Jump,
+    /// `u16` forward offset, jumps when the top of the stack is falsy without popping it
+    JumpIfFalse,
+    /// `u16` backward offset
+    Loop,
+    /// `u8` argument count, the callee is below the arguments
+    Call,
+    /// `u16` constant index of the function, then for each upvalue
+    /// a `u8` that is 1 when it captures a local of the enclosing function and its `u8` index
+    Closure,
+    /// Moves the local on top of the stack into the upvalues capturing it, then pops it
+    CloseUpvalue,
+    Return,
+}
+
+impl TryFrom<u8> for OpCode {
+    type Error = u8;
+
+    fn try_from(byte: u8) -> Result<Self, Self::Error> {
+        OpCode::ALL.get(byte as usize).copied().ok_or(byte)
+    }
+}
+
+/// A value known at compile time
+#[derive(Debug, Clone, PartialEq)]
+pub enum Constant {
+    Int(i64),
+    Float(f64),
+    Str(EcoString),
+    Function(Rc<FunctionProto>),
+}
+
+impl fmt::Display for Constant {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Constant::Int(value) => write!(f, "{}", value),
+            Constant::Float(value) => write!(f, "{:?}", value),
+            Constant::Str(value) => write!(f, "{:?}", value),
+            Constant::Function(function) => write!(f, "<fn {}>", function.name),
+        }
+    }
+}
+
+/// A compiled function, the top level of a program is compiled to one named `script`
+#[derive(Debug, Clone, PartialEq)]
+pub struct FunctionProto {
+    pub name: EcoString,
+    pub arity: usize,
+    /// Number of variables captured from enclosing functions
+    pub upvalue_count: usize,
+    pub chunk: Chunk,
+    /// Signature in the source, see `FunctionDecl::span`
+    pub span: Span,
+}
+
+/// Bytecode with its constant pool and the source span of every instruction
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Chunk {
+    pub code: Vec<u8>,
+    pub constants: Vec<Constant>,
+    /// `(offset, span)` where a run of instructions compiled from the same node starts
+    pub spans: Vec<(usize, Span)>,
+}
+
+impl Chunk {
+    pub fn write(&mut self, byte: u8, span: Span) {
+        if self.spans.last().is_none_or(|(_, last)| *last != span) {
+            self.spans.push((self.code.len(), span));
+        }
+        self.code.push(byte);
+    }
+
+    /// Add a constant, returning its index. Equal strings share one entry.
+    pub fn add_constant(&mut self, constant: Constant) -> usize {
+        if let Constant::Str(_) = constant {
+            if let Some(index) = self.constants.iter().position(|c| *c == constant) {
+                return index;
+            }
+        }
+        self.constants.push(constant);
+        self.constants.len() - 1
+    }
+
+    pub fn read_u16(&self, offset: usize) -> u16 {
+        u16::from_be_bytes([self.code[offset], self.code[offset + 1]])
+    }
+
+    /// Span of the node the instruction at `offset` was compiled from
+    pub fn span_at(&self, offset: usize) -> Span {
+        let index = self.spans.partition_point(|(start, _)| *start <= offset);
+        self.spans[index.saturating_sub(1)].1
+    }
+}
@@ -0,0 +1,194 @@
+use crate::gc::GcConfig;
+use std::io::IsTerminal;
+use std::path::PathBuf;
+use std::str::FromStr;
+
+pub const USAGE: &str = "\
+Usage: relox [options] [script.rx | script.rxc | -e <code> | -] [args...]
+       relox compile <script.rx> [-o <output.rxc>]
+
+Without a program, starts an interactive session when stdin is a terminal
+and reads the program from stdin otherwise. The session runs on the tree
+walking interpreter and takes the garbage collector options.
+
+Options:
+  --vm                  Run on the bytecode VM instead of the tree walking interpreter
+  --tokens              Print the tokens of the program instead of running it
+  --ast                 Print the syntax tree of the program instead of running it
+  --bytecode            Print the disassembled bytecode of the program instead of running it
+  --gc-stress           Collect garbage before every allocation
+  --gc-stats            Print garbage collector statistics when the program ends
+  --gc-threshold <n>    Live objects that trigger the first collection, 1024 by default
+  --gc-growth <factor>  Growth of the live objects that triggers the next collection, 2.0 by default
+  -e <code>             Run <code> instead of a script file
+  -                     Read the program from stdin
+  -h, --help            Print this help
+
+'relox compile' saves the compiled bytecode of a script, <script.rxc> by default,
+which then runs on the VM without being parsed again.
+
+The garbage collector options apply to both the tree walking interpreter
+and the VM.
+
+Arguments after the program are passed to it, see argc() and argv(i).";
+
+/// Where the program to run comes from
+#[derive(Debug)]
+pub enum Input {
+    File(PathBuf),
+    Eval(String),
+    Stdin,
+}
+
+/// How the program is executed
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Engine {
+    TreeWalker,
+    Vm,
+}
+
+/// Stages of the pipeline to print instead of running the program, in pipeline order
+#[derive(Debug, Default)]
+pub struct Dump {
+    pub tokens: bool,
+    pub ast: bool,
+    pub bytecode: bool,
+}
+
+impl Dump {
+    pub fn any(&self) -> bool {
+        self.tokens || self.ast || self.bytecode
+    }
+}
+
+#[derive(Debug)]
+pub struct Cli {
+    pub input: Input,
+    pub engine: Engine,
+    pub dump: Dump,
+    pub gc: GcConfig,
+    /// Print garbage collector statistics once the program ends
+    pub gc_stats: bool,
+    /// Arguments for the script itself
+    pub args: Vec<String>,
+}
+
+/// Result of parsing the command line
+#[derive(Debug)]
+pub enum Command {
+    Run(Cli),
+    /// Compile a script to a `.rxc` file, next to it unless `output` is given
+    Compile {
+        input: PathBuf,
+        output: Option<PathBuf>,
+    },
+    /// Interactive session, always on the tree walking interpreter
+    Repl {
+        gc: GcConfig,
+        gc_stats: bool,
+    },
+    Help,
+}
+
+impl Command {
+    /// Parse the arguments following the program name.
+    /// Everything after the script, `-e <code>` or `-` belongs to the script.
+    /// Without any argument it's the REPL, unless the program is piped into stdin.
+    pub fn parse(mut args: impl Iterator<Item = String>) -> Result<Command, String> {
+        let mut engine = Engine::TreeWalker;
+        let mut dump = Dump::default();
+        let mut gc = GcConfig::default();
+        let mut gc_stats = false;
+        let mut arg = args.next();
+        loop {
+            match arg.as_deref() {
+                Some("--vm") => engine = Engine::Vm,
+                Some("--tokens") => dump.tokens = true,
+                Some("--ast") => dump.ast = true,
+                Some("--bytecode") => dump.bytecode = true,
+                Some("--gc-stress") => gc.stress = true,
+                Some("--gc-stats") => gc_stats = true,
+                Some("--gc-threshold") => {
+                    gc.initial_threshold = number(&mut args, "--gc-threshold")?
+                }
+                Some("--gc-growth") => {
+                    gc.growth_factor = number(&mut args, "--gc-growth")?;
+                    // a factor below 1 would collect again before the heap grew back
+                    if gc.growth_factor < 1.0 || !gc.growth_factor.is_finite() {
+                        return Err("'--gc-growth' expects a factor of at least 1".into());
+                    }
+                }
+                _ => break,
+            }
+            arg = args.next();
+        }
+
+        let input = match arg.as_deref() {
+            None if std::io::stdin().is_terminal() => {
+                if engine == Engine::Vm {
+                    return Err("'--vm' needs a program, the REPL runs on the tree walker".into());
+                }
+                if dump.any() {
+                    return Err("'--tokens', '--ast' and '--bytecode' need a program".into());
+                }
+                return Ok(Command::Repl { gc, gc_stats });
+            }
+            None | Some("-") => Input::Stdin,
+            Some("-h" | "--help") => return Ok(Command::Help),
+            Some("compile") => return Command::parse_compile(args),
+            Some("-e") => match args.next() {
+                Some(code) => Input::Eval(code),
+                None => return Err("'-e' expects the code to run".into()),
+            },
+            Some(flag) if flag.starts_with('-') => {
+                return Err(format!("Unknown option '{}'", flag))
+            }
+            Some(path) => Input::File(path.into()),
+        };
+
+        Ok(Command::Run(Cli {
+            input,
+            engine,
+            dump,
+            gc,
+            gc_stats,
+            args: args.collect(),
+        }))
+    }
+
+    /// Parse the arguments of `relox compile`
+    /// E.g.
+    /// ```text
+    ///  relox compile script.rx -o build/script.rxc
+    ///```
+    fn parse_compile(mut args: impl Iterator<Item = String>) -> Result<Command, String> {
+        let mut input = None;
+        let mut output = None;
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "-o" => match args.next() {
+                    Some(path) => output = Some(path.into()),
+                    None => return Err("'-o' expects the output file".into()),
+                },
+                flag if flag.starts_with('-') => return Err(format!("Unknown option '{}'", flag)),
+                _ if input.is_some() => return Err("'compile' expects a single script".into()),
+                path => input = Some(path.into()),
+            }
+        }
+
+        match input {
+            Some(input) => Ok(Command::Compile { input, output }),
+            None => Err("'compile' expects the script to compile".into()),
+        }
+    }
+}
+
+/// Value of an option taking a number
+fn number<T: FromStr>(args: &mut impl Iterator<Item = String>, option: &str) -> Result<T, String> {
+    let value = args
+        .next()
+        .ok_or_else(|| format!("'{}' expects a number", option))?;
+    value
+        .parse()
+        .map_err(|_| format!("'{}' expects a number, got '{}'", option, value))
+}
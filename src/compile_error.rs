@@ -0,0 +1,39 @@
+use crate::diagnostic::Diagnostic;
+use crate::span::Span;
+use thiserror::Error;
+
+/// An error found while compiling to bytecode, before anything runs
+#[derive(Debug, Clone, Error)]
+#[error("{kind}")]
+pub struct CompileError {
+    pub kind: CompileErrorKind,
+    pub span: Span,
+}
+
+#[derive(Debug, Clone, Error)]
+pub enum CompileErrorKind {
+    #[error("Too many constants in one function, the limit is {0}")]
+    TooManyConstants(usize),
+    #[error("Too many local variables in one function, the limit is {0}")]
+    TooManyLocals(usize),
+    #[error("Too many captured variables in one function, the limit is {0}")]
+    TooManyUpvalues(usize),
+    #[error("Too many arguments in one call, the limit is {0}")]
+    TooManyArguments(usize),
+    #[error("Too many parts in one interpolated string, the limit is {0}")]
+    TooManyParts(usize),
+    #[error("Too much code to jump over, the limit is {0} bytes")]
+    JumpTooLarge(usize),
+}
+
+impl CompileError {
+    pub fn new(kind: CompileErrorKind, span: Span) -> Self {
+        CompileError { kind, span }
+    }
+}
+
+impl From<CompileError> for Diagnostic {
+    fn from(err: CompileError) -> Self {
+        Diagnostic::error(err.to_string(), err.span)
+    }
+}
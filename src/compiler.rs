@@ -0,0 +1,732 @@
+use crate::chunk::{Chunk, Constant, FunctionProto, OpCode};
+use crate::compile_error::{CompileError, CompileErrorKind};
+use crate::parser::{Expression, ExpressionKind, FunctionDecl, Statement, StatementKind};
+use crate::span::Span;
+use crate::token::TokenType;
+use ecow::EcoString;
+use std::rc::Rc;
+
+const MAX_LOCALS: usize = u8::MAX as usize + 1;
+const MAX_UPVALUES: usize = u8::MAX as usize + 1;
+const MAX_CONSTANTS: usize = u16::MAX as usize + 1;
+const MAX_ARGUMENTS: usize = u8::MAX as usize;
+const MAX_JUMP: usize = u16::MAX as usize;
+
+/// Variable living in a stack slot of its function's frame
+struct Local {
+    name: EcoString,
+    /// Scope depth it was declared at, 0 only for the callee in slot 0
+    depth: usize,
+    mutable: bool,
+    /// False while its initializer is compiled. Only closures created in the initializer
+    /// see it then, other uses find an outer variable like the tree walker does.
+    initialized: bool,
+    /// Some closure captures it, so leaving its scope has to close the upvalue
+    captured: bool,
+}
+
+/// Variable captured from an enclosing function
+struct Upvalue {
+    /// Slot of the enclosing function's local, or index in its upvalues
+    index: u8,
+    is_local: bool,
+    mutable: bool,
+}
+
+/// Innermost loop being compiled, with the jumps out of it still to be patched
+struct Loop {
+    /// Locals deeper than this belong to the loop body, `break` and `continue` pop them
+    depth: usize,
+    /// Where `continue` jumps back to, `None` when it jumps forward and is patched later
+    continue_to: Option<usize>,
+    continues: Vec<usize>,
+    breaks: Vec<usize>,
+}
+
+/// Compilation state of one function
+struct FunctionState {
+    proto: FunctionProto,
+    locals: Vec<Local>,
+    upvalues: Vec<Upvalue>,
+    scope_depth: usize,
+    loops: Vec<Loop>,
+}
+
+/// Where a variable lives, resolved at compile time
+enum Access {
+    Local(u8),
+    Upvalue(u8),
+    Global(u16),
+}
+
+/// Compiles a parsed program to bytecode for the `Vm`.
+/// Variables declared at the top level are globals looked up by name,
+/// every other variable gets a stack slot, or an upvalue when a closure captures it.
+pub struct Compiler {
+    /// Functions being compiled, the innermost last
+    functions: Vec<FunctionState>,
+}
+
+impl Compiler {
+    pub fn new() -> Self {
+        Compiler {
+            functions: Vec::new(),
+        }
+    }
+
+    pub fn compile(mut self, statements: &[Statement]) -> Result<Rc<FunctionProto>, CompileError> {
+        self.begin_function("script".into(), 0, Span::default());
+        for stmt in statements {
+            self.statement(stmt)?;
+        }
+
+        let end = statements.last().map_or(Span::default(), |stmt| {
+            Span::new(stmt.span.end, stmt.span.end)
+        });
+        let (script, _) = self.end_function(end);
+        Ok(Rc::new(script))
+    }
+
+    fn state(&mut self) -> &mut FunctionState {
+        self.functions.last_mut().unwrap()
+    }
+
+    fn chunk(&mut self) -> &mut Chunk {
+        &mut self.state().proto.chunk
+    }
+
+    fn emit(&mut self, op: OpCode, span: Span) {
+        self.chunk().write(op as u8, span);
+    }
+
+    fn emit_byte(&mut self, byte: u8, span: Span) {
+        self.chunk().write(byte, span);
+    }
+
+    fn emit_u16(&mut self, value: u16, span: Span) {
+        for byte in value.to_be_bytes() {
+            self.chunk().write(byte, span);
+        }
+    }
+
+    fn constant(&mut self, constant: Constant, span: Span) -> Result<u16, CompileError> {
+        let index = self.chunk().add_constant(constant);
+        u16::try_from(index)
+            .map_err(|_| CompileError::new(CompileErrorKind::TooManyConstants(MAX_CONSTANTS), span))
+    }
+
+    fn emit_constant(&mut self, constant: Constant, span: Span) -> Result<(), CompileError> {
+        let index = self.constant(constant, span)?;
+        self.emit(OpCode::Constant, span);
+        self.emit_u16(index, span);
+        Ok(())
+    }
+
+    /// Emit a forward jump, returning the offset of its operand for `patch_jump`
+    fn emit_jump(&mut self, op: OpCode, span: Span) -> usize {
+        self.emit(op, span);
+        self.emit_u16(u16::MAX, span);
+        self.chunk().code.len() - 2
+    }
+
+    /// Point the jump with its operand at `offset` to the next instruction
+    fn patch_jump(&mut self, offset: usize, span: Span) -> Result<(), CompileError> {
+        let chunk = self.chunk();
+        let distance = chunk.code.len() - offset - 2;
+        let distance = u16::try_from(distance)
+            .map_err(|_| CompileError::new(CompileErrorKind::JumpTooLarge(MAX_JUMP), span))?;
+        chunk.code[offset..offset + 2].copy_from_slice(&distance.to_be_bytes());
+        Ok(())
+    }
+
+    /// Emit a jump back to `start`
+    fn emit_loop(&mut self, start: usize, span: Span) -> Result<(), CompileError> {
+        self.emit(OpCode::Loop, span);
+        let distance = self.chunk().code.len() - start + 2;
+        let distance = u16::try_from(distance)
+            .map_err(|_| CompileError::new(CompileErrorKind::JumpTooLarge(MAX_JUMP), span))?;
+        self.emit_u16(distance, span);
+        Ok(())
+    }
+
+    fn begin_function(&mut self, name: EcoString, arity: usize, span: Span) {
+        self.functions.push(FunctionState {
+            proto: FunctionProto {
+                name,
+                arity,
+                upvalue_count: 0,
+                chunk: Chunk::default(),
+                span,
+            },
+            // slot 0 holds the function being called
+            locals: vec![Local {
+                name: EcoString::new(),
+                depth: 0,
+                mutable: false,
+                initialized: true,
+                captured: false,
+            }],
+            upvalues: Vec::new(),
+            scope_depth: 0,
+            loops: Vec::new(),
+        });
+    }
+
+    /// Finish the innermost function, falling off its end returns nil
+    fn end_function(&mut self, span: Span) -> (FunctionProto, Vec<Upvalue>) {
+        self.emit(OpCode::Nil, span);
+        self.emit(OpCode::Return, span);
+
+        let mut state = self.functions.pop().unwrap();
+        state.proto.upvalue_count = state.upvalues.len();
+        (state.proto, state.upvalues)
+    }
+
+    fn begin_scope(&mut self) {
+        self.state().scope_depth += 1;
+    }
+
+    fn end_scope(&mut self, span: Span) {
+        let state = self.state();
+        state.scope_depth -= 1;
+        let depth = state.scope_depth;
+
+        self.pop_locals(depth, span);
+        let state = self.state();
+        let count = state.locals.iter().filter(|l| l.depth > depth).count();
+        state.locals.truncate(state.locals.len() - count);
+    }
+
+    /// Emit the code dropping every local deeper than `depth`, without forgetting them
+    fn pop_locals(&mut self, depth: usize, span: Span) {
+        let ops: Vec<OpCode> = self
+            .state()
+            .locals
+            .iter()
+            .rev()
+            .take_while(|local| local.depth > depth)
+            .map(|local| {
+                if local.captured {
+                    OpCode::CloseUpvalue
+                } else {
+                    OpCode::Pop
+                }
+            })
+            .collect();
+
+        for op in ops {
+            self.emit(op, span);
+        }
+    }
+
+    /// Turn the value on top of the stack into a local of the current scope
+    fn declare_local(
+        &mut self,
+        name: &EcoString,
+        mutable: bool,
+        span: Span,
+    ) -> Result<(), CompileError> {
+        let state = self.state();
+        let depth = state.scope_depth;
+
+        if state.locals.len() == MAX_LOCALS {
+            let kind = CompileErrorKind::TooManyLocals(MAX_LOCALS);
+            return Err(CompileError::new(kind, span));
+        }
+
+        state.locals.push(Local {
+            name: name.clone(),
+            depth,
+            mutable,
+            initialized: true,
+            captured: false,
+        });
+        Ok(())
+    }
+
+    /// Bind the value on top of the stack to `name`, as a global at the top level
+    fn define_variable(
+        &mut self,
+        name: &EcoString,
+        mutable: bool,
+        span: Span,
+    ) -> Result<(), CompileError> {
+        if self.state().scope_depth > 0 {
+            return self.declare_local(name, mutable, span);
+        }
+
+        let index = self.constant(Constant::Str(name.clone()), span)?;
+        let op = if mutable {
+            OpCode::DefineGlobal
+        } else {
+            OpCode::DefineConstGlobal
+        };
+        self.emit(op, span);
+        self.emit_u16(index, span);
+        Ok(())
+    }
+
+    /// Find `name` in the scopes of the function at `function`, innermost first.
+    /// A local still being initialized is only found from a function nested in the initializer.
+    fn resolve_local(&self, function: usize, name: &str) -> Option<(u8, bool)> {
+        let nested = function + 1 < self.functions.len();
+        self.functions[function]
+            .locals
+            .iter()
+            .enumerate()
+            .rev()
+            .find(|(_, local)| {
+                local.depth > 0 && local.name == name && (local.initialized || nested)
+            })
+            .map(|(slot, local)| (slot as u8, local.mutable))
+    }
+
+    /// Compile a `let` or `const`. A local is declared before its initializer is compiled,
+    /// so a closure created in the initializer can refer to it, like a local function can.
+    fn variable(
+        &mut self,
+        name: &EcoString,
+        value: &Expression,
+        mutable: bool,
+        span: Span,
+    ) -> Result<(), CompileError> {
+        if self.state().scope_depth == 0 {
+            self.expression(value)?;
+            return self.define_variable(name, mutable, span);
+        }
+
+        self.declare_local(name, mutable, span)?;
+        let slot = self.state().locals.len() - 1;
+        self.state().locals[slot].initialized = false;
+        self.expression(value)?;
+        self.state().locals[slot].initialized = true;
+        Ok(())
+    }
+
+    /// Find `name` in the functions enclosing the one at `function`,
+    /// adding an upvalue to every function in between
+    fn resolve_upvalue(
+        &mut self,
+        function: usize,
+        name: &str,
+        span: Span,
+    ) -> Result<Option<(u8, bool)>, CompileError> {
+        if function == 0 {
+            return Ok(None);
+        }
+
+        if let Some((slot, mutable)) = self.resolve_local(function - 1, name) {
+            self.functions[function - 1].locals[slot as usize].captured = true;
+            return self
+                .add_upvalue(function, slot, true, mutable, span)
+                .map(Some);
+        }
+
+        match self.resolve_upvalue(function - 1, name, span)? {
+            Some((index, mutable)) => self
+                .add_upvalue(function, index, false, mutable, span)
+                .map(Some),
+            None => Ok(None),
+        }
+    }
+
+    fn add_upvalue(
+        &mut self,
+        function: usize,
+        index: u8,
+        is_local: bool,
+        mutable: bool,
+        span: Span,
+    ) -> Result<(u8, bool), CompileError> {
+        let upvalues = &mut self.functions[function].upvalues;
+        if let Some((existing, up)) = upvalues
+            .iter()
+            .enumerate()
+            .find(|(_, up)| up.index == index && up.is_local == is_local)
+        {
+            return Ok((existing as u8, up.mutable));
+        }
+
+        if upvalues.len() == MAX_UPVALUES {
+            let kind = CompileErrorKind::TooManyUpvalues(MAX_UPVALUES);
+            return Err(CompileError::new(kind, span));
+        }
+
+        upvalues.push(Upvalue {
+            index,
+            is_local,
+            mutable,
+        });
+        Ok(((upvalues.len() - 1) as u8, mutable))
+    }
+
+    /// Resolve a variable, whether it is mutable is only known for locals and upvalues
+    fn resolve(&mut self, name: &EcoString, span: Span) -> Result<(Access, bool), CompileError> {
+        let function = self.functions.len() - 1;
+        if let Some((slot, mutable)) = self.resolve_local(function, name) {
+            return Ok((Access::Local(slot), mutable));
+        }
+        if let Some((index, mutable)) = self.resolve_upvalue(function, name, span)? {
+            return Ok((Access::Upvalue(index), mutable));
+        }
+
+        // globals are checked when the assignment runs
+        let index = self.constant(Constant::Str(name.clone()), span)?;
+        Ok((Access::Global(index), true))
+    }
+
+    fn statement(&mut self, stmt: &Statement) -> Result<(), CompileError> {
+        let span = stmt.span;
+
+        match &stmt.kind {
+            StatementKind::Let(name, expr) => self.variable(name, expr, true, span)?,
+            StatementKind::Const(name, expr) => self.variable(name, expr, false, span)?,
+            StatementKind::Print(expr) => {
+                self.expression(expr)?;
+                self.emit(OpCode::Print, span);
+            }
+            StatementKind::Expression(expr) => {
+                self.expression(expr)?;
+                self.emit(OpCode::Pop, span);
+            }
+            StatementKind::If(condition, then_branch, else_branch) => {
+                self.expression(condition)?;
+                let then_jump = self.emit_jump(OpCode::JumpIfFalse, span);
+                self.emit(OpCode::Pop, span);
+                self.block(then_branch, span)?;
+
+                let else_jump = self.emit_jump(OpCode::Jump, span);
+                self.patch_jump(then_jump, span)?;
+                self.emit(OpCode::Pop, span);
+                if let Some(else_branch) = else_branch {
+                    self.block(else_branch, span)?;
+                }
+                self.patch_jump(else_jump, span)?;
+            }
+            StatementKind::Block(statements) => self.block(statements, span)?,
+            StatementKind::While(condition, body) => {
+                let start = self.chunk().code.len();
+                self.expression(condition)?;
+                let exit = self.emit_jump(OpCode::JumpIfFalse, span);
+                self.emit(OpCode::Pop, span);
+
+                let depth = self.state().scope_depth;
+                self.begin_loop(depth, Some(start));
+                self.block(body, span)?;
+                self.emit_loop(start, span)?;
+
+                self.patch_jump(exit, span)?;
+                self.emit(OpCode::Pop, span);
+                self.end_loop(span)?;
+            }
+            StatementKind::For(name, start, end, body) => {
+                self.for_loop(name, start, end, body, span)?
+            }
+            StatementKind::Break => {
+                let depth = self.state().loops.last().unwrap().depth;
+                self.pop_locals(depth, span);
+                let jump = self.emit_jump(OpCode::Jump, span);
+                self.state().loops.last_mut().unwrap().breaks.push(jump);
+            }
+            StatementKind::Continue => {
+                let (depth, continue_to) = {
+                    let innermost = self.state().loops.last().unwrap();
+                    (innermost.depth, innermost.continue_to)
+                };
+                self.pop_locals(depth, span);
+                match continue_to {
+                    Some(start) => self.emit_loop(start, span)?,
+                    None => {
+                        let jump = self.emit_jump(OpCode::Jump, span);
+                        self.state().loops.last_mut().unwrap().continues.push(jump);
+                    }
+                }
+            }
+            StatementKind::Function(decl) => {
+                // a local function is declared first so its body can call it recursively
+                if self.state().scope_depth > 0 {
+                    self.declare_local(&decl.name, true, span)?;
+                    self.function(decl)?;
+                } else {
+                    self.function(decl)?;
+                    self.define_variable(&decl.name, true, span)?;
+                }
+            }
+            StatementKind::Return(value) => {
+                match value {
+                    Some(expr) => self.expression(expr)?,
+                    None => self.emit(OpCode::Nil, span),
+                }
+                self.emit(OpCode::Return, span);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Compile statements in a scope of their own
+    fn block(&mut self, statements: &[Statement], span: Span) -> Result<(), CompileError> {
+        self.begin_scope();
+        for stmt in statements {
+            self.statement(stmt)?;
+        }
+        self.end_scope(span);
+        Ok(())
+    }
+
+    fn begin_loop(&mut self, depth: usize, continue_to: Option<usize>) {
+        self.state().loops.push(Loop {
+            depth,
+            continue_to,
+            continues: Vec::new(),
+            breaks: Vec::new(),
+        });
+    }
+
+    /// Patch the `break`s of the innermost loop to jump here
+    fn end_loop(&mut self, span: Span) -> Result<(), CompileError> {
+        let innermost = self.state().loops.pop().unwrap();
+        for jump in innermost.breaks {
+            self.patch_jump(jump, span)?;
+        }
+        Ok(())
+    }
+
+    /// `for name in start..end { body }` keeps the next index and the end in hidden locals.
+    /// Each iteration binds `name` in a fresh scope, so closures capture that iteration's value.
+    fn for_loop(
+        &mut self,
+        name: &EcoString,
+        start: &Expression,
+        end: &Expression,
+        body: &[Statement],
+        span: Span,
+    ) -> Result<(), CompileError> {
+        self.begin_scope();
+        self.expression(start)?;
+        self.emit(OpCode::CheckInt, start.span);
+        self.declare_local(&"(index)".into(), true, span)?;
+        self.expression(end)?;
+        self.emit(OpCode::CheckInt, end.span);
+        self.declare_local(&"(end)".into(), true, span)?;
+
+        let state = self.state();
+        let index = (state.locals.len() - 2) as u8;
+        let depth = state.scope_depth;
+
+        let loop_start = self.chunk().code.len();
+        self.emit(OpCode::GetLocal, span);
+        self.emit_byte(index, span);
+        self.emit(OpCode::GetLocal, span);
+        self.emit_byte(index + 1, span);
+        self.emit(OpCode::Less, span);
+        let exit = self.emit_jump(OpCode::JumpIfFalse, span);
+        self.emit(OpCode::Pop, span);
+
+        self.begin_loop(depth, None);
+        self.begin_scope();
+        self.emit(OpCode::GetLocal, span);
+        self.emit_byte(index, span);
+        self.declare_local(name, true, span)?;
+        for stmt in body {
+            self.statement(stmt)?;
+        }
+        self.end_scope(span);
+
+        // `continue` lands on the increment
+        let continues = std::mem::take(&mut self.state().loops.last_mut().unwrap().continues);
+        for jump in continues {
+            self.patch_jump(jump, span)?;
+        }
+        self.emit(OpCode::GetLocal, span);
+        self.emit_byte(index, span);
+        self.emit_constant(Constant::Int(1), span)?;
+        self.emit(OpCode::Add, span);
+        self.emit(OpCode::SetLocal, span);
+        self.emit_byte(index, span);
+        self.emit(OpCode::Pop, span);
+        self.emit_loop(loop_start, span)?;
+
+        self.patch_jump(exit, span)?;
+        self.emit(OpCode::Pop, span);
+        self.end_loop(span)?;
+        self.end_scope(span);
+        Ok(())
+    }
+
+    /// Compile a function declaration or lambda to code creating a closure of it
+    fn function(&mut self, decl: &FunctionDecl) -> Result<(), CompileError> {
+        let span = decl.span;
+
+        self.begin_function(decl.name.clone(), decl.params.len(), span);
+        self.begin_scope();
+        for param in &decl.params {
+            self.declare_local(param, true, span)?;
+        }
+        for stmt in &decl.body {
+            self.statement(stmt)?;
+        }
+        let (proto, upvalues) = self.end_function(span);
+
+        let index = self.constant(Constant::Function(Rc::new(proto)), span)?;
+        self.emit(OpCode::Closure, span);
+        self.emit_u16(index, span);
+        for upvalue in upvalues {
+            self.emit_byte(upvalue.is_local as u8, span);
+            self.emit_byte(upvalue.index, span);
+        }
+        Ok(())
+    }
+
+    fn expression(&mut self, expr: &Expression) -> Result<(), CompileError> {
+        let span = expr.span;
+
+        match &expr.kind {
+            ExpressionKind::Int(value) => self.emit_constant(Constant::Int(*value), span)?,
+            ExpressionKind::Float(value) => self.emit_constant(Constant::Float(*value), span)?,
+            ExpressionKind::Str(value) => self.emit_constant(Constant::Str(value.clone()), span)?,
+            ExpressionKind::Interpolation(parts) => {
+                for part in parts {
+                    self.expression(part)?;
+                }
+                let count = u16::try_from(parts.len()).map_err(|_| {
+                    let limit = u16::MAX as usize;
+                    CompileError::new(CompileErrorKind::TooManyParts(limit), span)
+                })?;
+                self.emit(OpCode::BuildString, span);
+                self.emit_u16(count, span);
+            }
+            ExpressionKind::Bool(true) => self.emit(OpCode::True, span),
+            ExpressionKind::Bool(false) => self.emit(OpCode::False, span),
+            ExpressionKind::Nil => self.emit(OpCode::Nil, span),
+            ExpressionKind::Identifier(name, _) => match self.resolve(name, span)?.0 {
+                Access::Local(slot) => {
+                    self.emit(OpCode::GetLocal, span);
+                    self.emit_byte(slot, span);
+                }
+                Access::Upvalue(index) => {
+                    self.emit(OpCode::GetUpvalue, span);
+                    self.emit_byte(index, span);
+                }
+                Access::Global(index) => {
+                    self.emit(OpCode::GetGlobal, span);
+                    self.emit_u16(index, span);
+                }
+            },
+            ExpressionKind::Assign(name, _, value) => {
+                self.expression(value)?;
+                let (access, mutable) = self.resolve(name, span)?;
+                if !mutable {
+                    // fails when it runs, like assigning to a global constant
+                    let index = self.constant(Constant::Str(name.clone()), span)?;
+                    self.emit(OpCode::AssignConstant, span);
+                    self.emit_u16(index, span);
+                    return Ok(());
+                }
+
+                match access {
+                    Access::Local(slot) => {
+                        self.emit(OpCode::SetLocal, span);
+                        self.emit_byte(slot, span);
+                    }
+                    Access::Upvalue(index) => {
+                        self.emit(OpCode::SetUpvalue, span);
+                        self.emit_byte(index, span);
+                    }
+                    Access::Global(index) => {
+                        self.emit(OpCode::SetGlobal, span);
+                        self.emit_u16(index, span);
+                    }
+                }
+            }
+            ExpressionKind::Negation(operand) => {
+                self.expression(operand)?;
+                self.emit(OpCode::Negate, span);
+            }
+            ExpressionKind::Not(operand) => {
+                self.expression(operand)?;
+                self.emit(OpCode::Not, span);
+            }
+            ExpressionKind::Binary(left, op, right) => {
+                self.expression(left)?;
+                self.expression(right)?;
+                let op = match op {
+                    TokenType::Equal => OpCode::Equal,
+                    TokenType::NotEqual => OpCode::NotEqual,
+                    TokenType::Lt => OpCode::Less,
+                    TokenType::Gt => OpCode::Greater,
+                    TokenType::LtEq => OpCode::LessEqual,
+                    TokenType::GtEq => OpCode::GreaterEqual,
+                    TokenType::Plus => OpCode::Add,
+                    TokenType::Minus => OpCode::Subtract,
+                    TokenType::Star => OpCode::Multiply,
+                    TokenType::Slash => OpCode::Divide,
+                    _ => unreachable!(
+                        "the parser only builds binary expressions of binary operators"
+                    ),
+                };
+                self.emit(op, span);
+            }
+            ExpressionKind::Logical(left, op, right) => {
+                // The result is a bool, so the right side is negated twice to convert it
+                self.expression(left)?;
+                let skip_right = self.emit_jump(OpCode::JumpIfFalse, span);
+                self.emit(OpCode::Pop, span);
+
+                if *op == TokenType::And {
+                    self.expression(right)?;
+                    self.emit(OpCode::Not, span);
+                    self.emit(OpCode::Not, span);
+                    let end = self.emit_jump(OpCode::Jump, span);
+                    self.patch_jump(skip_right, span)?;
+                    self.emit(OpCode::Pop, span);
+                    self.emit(OpCode::False, span);
+                    self.patch_jump(end, span)?;
+                } else {
+                    self.emit(OpCode::True, span);
+                    let end = self.emit_jump(OpCode::Jump, span);
+                    self.patch_jump(skip_right, span)?;
+                    self.emit(OpCode::Pop, span);
+                    self.expression(right)?;
+                    self.emit(OpCode::Not, span);
+                    self.emit(OpCode::Not, span);
+                    self.patch_jump(end, span)?;
+                }
+            }
+            ExpressionKind::Call(callee, args) => {
+                self.expression(callee)?;
+                for arg in args {
+                    self.expression(arg)?;
+                }
+                if args.len() > MAX_ARGUMENTS {
+                    let kind = CompileErrorKind::TooManyArguments(MAX_ARGUMENTS);
+                    return Err(CompileError::new(kind, span));
+                }
+                self.emit(OpCode::Call, span);
+                self.emit_byte(args.len() as u8, span);
+            }
+            ExpressionKind::Index(value, index) => {
+                self.expression(value)?;
+                self.expression(index)?;
+                self.emit(OpCode::Index, span);
+            }
+            ExpressionKind::Slice(value, start, end) => {
+                self.expression(value)?;
+                let mut flags = 0;
+                if let Some(start) = start {
+                    self.expression(start)?;
+                    flags |= 1;
+                }
+                if let Some(end) = end {
+                    self.expression(end)?;
+                    flags |= 2;
+                }
+                self.emit(OpCode::Slice, span);
+                self.emit_byte(flags, span);
+            }
+            ExpressionKind::Lambda(decl) => self.function(decl)?,
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,170 @@
+use crate::span::{SourceMap, Span};
+use colored::Colorize;
+use std::fmt::Write;
+use std::io::IsTerminal;
+
+/// A message attached to a span of source code
+#[derive(Debug, Clone)]
+pub struct Label {
+    pub span: Span,
+    pub message: String,
+}
+
+/// An error pointing at source code, rendered with the offending line underlined.
+/// The primary span is underlined with `^`, secondary labels with `-`.
+#[derive(Debug, Clone)]
+pub struct Diagnostic {
+    pub message: String,
+    pub span: Span,
+    /// Text shown next to the primary underline
+    pub label: Option<String>,
+    pub secondary: Vec<Label>,
+    pub help: Vec<String>,
+}
+
+impl Diagnostic {
+    pub fn error(message: impl Into<String>, span: Span) -> Self {
+        Diagnostic {
+            message: message.into(),
+            span,
+            label: None,
+            secondary: Vec::new(),
+            help: Vec::new(),
+        }
+    }
+
+    pub fn with_label(mut self, label: impl Into<String>) -> Self {
+        self.label = Some(label.into());
+        self
+    }
+
+    pub fn with_secondary(mut self, span: Span, message: impl Into<String>) -> Self {
+        self.secondary.push(Label {
+            span,
+            message: message.into(),
+        });
+        self
+    }
+
+    pub fn with_help(mut self, help: impl Into<String>) -> Self {
+        self.help.push(help.into());
+        self
+    }
+
+    /// Print the diagnostic to stderr
+    pub fn emit(&self, source: &SourceMap) {
+        eprint!("{}", self.render(source));
+    }
+
+    /// Render the diagnostic like
+    /// ```text
+    /// error: Division by zero
+    ///   --> script.rx:3:9
+    ///    |
+    ///  3 | print 1 / 0;
+    ///    |       ^^^^^ attempt to divide by zero
+    /// ```
+    pub fn render(&self, source: &SourceMap) -> String {
+        let (line, column) = source.line_col(self.span.start);
+
+        let mut labels = vec![(self.span, self.label.as_deref().unwrap_or(""), true)];
+        labels.extend(
+            self.secondary
+                .iter()
+                .map(|label| (label.span, label.message.as_str(), false)),
+        );
+        labels.sort_by_key(|(span, _, _)| span.start);
+
+        let last_line = labels
+            .iter()
+            .map(|(span, _, _)| source.line_col(span.start).0)
+            .max()
+            .unwrap_or(line);
+        let gutter = " ".repeat(last_line.to_string().len());
+
+        let mut out = String::new();
+        let _ = writeln!(
+            out,
+            "{}{}",
+            "error".red().bold(),
+            format!(": {}", self.message).bold()
+        );
+        let _ = writeln!(
+            out,
+            "{}{} {}:{}:{}",
+            gutter,
+            "-->".blue().bold(),
+            source.name,
+            line,
+            column
+        );
+        let _ = writeln!(out, "{} {}", gutter, "|".blue().bold());
+
+        let mut previous_line = None;
+        for (span, message, primary) in labels {
+            let (label_line, label_column) = source.line_col(span.start);
+            let text = source.line_text(label_line);
+
+            if previous_line != Some(label_line) {
+                if previous_line.is_some_and(|previous| label_line > previous + 1) {
+                    let _ = writeln!(out, "{}", "...".blue().bold());
+                }
+                let _ = writeln!(
+                    out,
+                    "{} {} {}",
+                    format!("{:>width$}", label_line, width = gutter.len())
+                        .blue()
+                        .bold(),
+                    "|".blue().bold(),
+                    text
+                );
+                previous_line = Some(label_line);
+            }
+
+            // Spans running past the end of their line are underlined up to the line end
+            let (end_line, end_column) = source.line_col(span.end);
+            let line_len = text.chars().count();
+            let width = if end_line == label_line {
+                end_column.saturating_sub(label_column)
+            } else {
+                (line_len + 1).saturating_sub(label_column)
+            }
+            .max(1);
+
+            let marker = if primary { "^" } else { "-" }.repeat(width);
+            let underline = format!("{} {}", marker, message).trim_end().to_string();
+            let underline = if primary {
+                underline.red().bold()
+            } else {
+                underline.blue().bold()
+            };
+            let _ = writeln!(
+                out,
+                "{} {} {}{}",
+                gutter,
+                "|".blue().bold(),
+                " ".repeat(label_column - 1),
+                underline
+            );
+        }
+
+        for help in &self.help {
+            let _ = writeln!(
+                out,
+                "{} {} {}",
+                gutter,
+                "=".blue().bold(),
+                format!("help: {}", help).bold()
+            );
+        }
+
+        out
+    }
+}
+
+/// Only color diagnostics when stderr is a terminal, so redirected output stays plain
+pub fn init_color() {
+    if !std::io::stderr().is_terminal() {
+        colored::control::set_override(false);
+    }
+}
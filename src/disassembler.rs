@@ -0,0 +1,146 @@
+use crate::chunk::{Chunk, Constant, FunctionProto, OpCode};
+use crate::span::SourceMap;
+use std::fmt::Write;
+
+/// Human readable listing of a compiled program: the offset, opcode and operands
+/// of every instruction, with the source line it was compiled from above it.
+/// Functions are listed after the code creating them.
+/// E.g.
+/// ```
+///  == <fn script> arity 0, 0 upvalue(s) ==
+///     1 | let x = 1 + 2;
+///  0000  Constant          0  1
+///  0003  Constant          1  2
+///  0006  Add
+///```
+pub fn disassemble(script: &FunctionProto, source_map: &SourceMap) -> String {
+    let mut disassembler = Disassembler {
+        out: String::new(),
+        source_map,
+    };
+    disassembler.function(script);
+    disassembler.out
+}
+
+struct Disassembler<'a> {
+    out: String,
+    source_map: &'a SourceMap,
+}
+
+impl Disassembler<'_> {
+    fn function(&mut self, function: &FunctionProto) {
+        let _ = writeln!(
+            self.out,
+            "== <fn {}> arity {}, {} upvalue(s) ==",
+            function.name, function.arity, function.upvalue_count
+        );
+
+        let chunk = &function.chunk;
+        let mut offset = 0;
+        let mut last_line = 0;
+        while offset < chunk.code.len() {
+            let (line, _) = self.source_map.line_col(chunk.span_at(offset).start);
+            if line != last_line {
+                let text = self.source_map.line_text(line).trim();
+                let _ = writeln!(self.out, "{:>4} | {}", line, text);
+                last_line = line;
+            }
+            offset = self.instruction(chunk, offset);
+        }
+
+        for constant in &chunk.constants {
+            if let Constant::Function(nested) = constant {
+                self.out.push('\n');
+                self.function(nested);
+            }
+        }
+    }
+
+    /// Write the instruction at `offset`, returning the offset of the next one
+    fn instruction(&mut self, chunk: &Chunk, offset: usize) -> usize {
+        let op = match OpCode::try_from(chunk.code[offset]) {
+            Ok(op) => op,
+            Err(byte) => {
+                let _ = writeln!(self.out, "{:04}  <invalid opcode {}>", offset, byte);
+                return offset + 1;
+            }
+        };
+        let name = format!("{:?}", op);
+        let byte = |i: usize| chunk.code[offset + i];
+
+        let (operands, size) = match op {
+            OpCode::Constant
+            | OpCode::GetGlobal
+            | OpCode::SetGlobal
+            | OpCode::DefineGlobal
+            | OpCode::DefineConstGlobal
+            | OpCode::AssignConstant => {
+                let index = chunk.read_u16(offset + 1);
+                let constant = &chunk.constants[index as usize];
+                (format!("{:>4}  {}", index, constant), 3)
+            }
+            OpCode::GetLocal
+            | OpCode::SetLocal
+            | OpCode::GetUpvalue
+            | OpCode::SetUpvalue
+            | OpCode::Call => (format!("{:>4}", byte(1)), 2),
+            OpCode::Slice => {
+                let start = if byte(1) & 1 != 0 { "start" } else { "" };
+                let end = if byte(1) & 2 != 0 { "end" } else { "" };
+                (format!("{:>4}  [{}..{}]", byte(1), start, end), 2)
+            }
+            OpCode::BuildString => (format!("{:>4}", chunk.read_u16(offset + 1)), 3),
+            OpCode::Jump | OpCode::JumpIfFalse => {
+                let distance = chunk.read_u16(offset + 1) as usize;
+                (
+                    format!("{:>4}  -> {:04}", distance, offset + 3 + distance),
+                    3,
+                )
+            }
+            OpCode::Loop => {
+                let distance = chunk.read_u16(offset + 1) as usize;
+                (
+                    format!("{:>4}  -> {:04}", distance, offset + 3 - distance),
+                    3,
+                )
+            }
+            OpCode::Closure => {
+                let index = chunk.read_u16(offset + 1);
+                let constant = &chunk.constants[index as usize];
+                let _ = writeln!(
+                    self.out,
+                    "{:04}  {:<16}{:>4}  {}",
+                    offset, name, index, constant
+                );
+
+                let Constant::Function(function) = constant else {
+                    return offset + 3;
+                };
+                // each captured variable is described by two operand bytes
+                let mut next = offset + 3;
+                for _ in 0..function.upvalue_count {
+                    let kind = if chunk.code[next] == 1 {
+                        "local"
+                    } else {
+                        "upvalue"
+                    };
+                    let _ = writeln!(
+                        self.out,
+                        "{:04}  {:>16}  {} {}",
+                        next,
+                        "|",
+                        kind,
+                        chunk.code[next + 1]
+                    );
+                    next += 2;
+                }
+                return next;
+            }
+            _ => (String::new(), 1),
+        };
+
+        let line = format!("{:04}  {:<16}{}", offset, name, operands);
+        let _ = writeln!(self.out, "{}", line.trim_end());
+        offset + size
+    }
+}
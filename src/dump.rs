@@ -0,0 +1,195 @@
+use crate::diagnostic::Diagnostic;
+use crate::lexer::Lexer;
+use crate::output;
+use crate::parser::{Expression, ExpressionKind, FunctionDecl, Slot, Statement, StatementKind};
+use crate::parser_error::ParseError;
+use crate::span::SourceMap;
+use crate::token::TokenType;
+use std::fmt::Write;
+
+/// Print every token of `source` with its position and byte range.
+/// Lexer errors are reported as diagnostics and lexing carries on after them.
+/// Returns whether the source lexed without errors.
+/// E.g.
+/// ```
+///  1:1     0..3     Let
+///  1:5     4..5     Identifier("x")
+///```
+pub fn tokens(source: &str, source_map: &SourceMap) -> bool {
+    let mut lexer = Lexer::new(source);
+    let mut ok = true;
+
+    loop {
+        match lexer.get_next_token() {
+            Ok(token) => {
+                let (line, col) = source_map.line_col(token.span.start);
+                let position = format!("{}:{}", line, col);
+                let range = format!("{}..{}", token.span.start, token.span.end);
+                output::println(format_args!("{:<8}{:<9}{:?}", position, range, token.token));
+
+                if token.token == TokenType::EOF {
+                    return ok;
+                }
+            }
+            Err(err) => {
+                let span = lexer.error_span();
+                Diagnostic::from(ParseError::new(err.into(), span)).emit(source_map);
+                ok = false;
+            }
+        }
+    }
+}
+
+/// Render parsed statements as an indented tree, one node per line
+/// E.g.
+/// ```
+///  Let x
+///    Binary Plus
+///      Int 1
+///      Identifier y (global)
+///```
+pub fn ast(statements: &[Statement]) -> String {
+    let mut printer = AstPrinter {
+        out: String::new(),
+        indent: 0,
+    };
+    for stmt in statements {
+        printer.statement(stmt);
+    }
+    printer.out
+}
+
+/// Variable name with where the `Resolver` found it
+fn variable(name: &str, slot: Option<Slot>) -> String {
+    match slot {
+        Some(Slot { depth, index }) => format!("{} (depth {}, slot {})", name, depth, index),
+        None => format!("{} (global)", name),
+    }
+}
+
+struct AstPrinter {
+    out: String,
+    indent: usize,
+}
+
+impl AstPrinter {
+    fn line(&mut self, text: &str) {
+        let _ = writeln!(self.out, "{:width$}{}", "", text, width = self.indent * 2);
+    }
+
+    /// Print `text` with `children` nested below it
+    fn node(&mut self, text: &str, children: impl FnOnce(&mut Self)) {
+        self.line(text);
+        self.indent += 1;
+        children(self);
+        self.indent -= 1;
+    }
+
+    fn block(&mut self, label: &str, statements: &[Statement]) {
+        self.node(label, |printer| {
+            for stmt in statements {
+                printer.statement(stmt);
+            }
+        });
+    }
+
+    fn function(&mut self, keyword: &str, decl: &FunctionDecl) {
+        let text = format!("{} {}({})", keyword, decl.name, decl.params.join(", "));
+        self.block(&text, &decl.body);
+    }
+
+    fn statement(&mut self, stmt: &Statement) {
+        match &stmt.kind {
+            StatementKind::Let(name, value) => {
+                self.node(&format!("Let {}", name), |p| p.expression(value))
+            }
+            StatementKind::Const(name, value) => {
+                self.node(&format!("Const {}", name), |p| p.expression(value))
+            }
+            StatementKind::Print(value) => self.node("Print", |p| p.expression(value)),
+            StatementKind::Expression(expr) => self.node("Expression", |p| p.expression(expr)),
+            StatementKind::If(condition, then_branch, else_branch) => self.node("If", |p| {
+                p.expression(condition);
+                p.block("Then", then_branch);
+                if let Some(else_branch) = else_branch {
+                    p.block("Else", else_branch);
+                }
+            }),
+            StatementKind::Block(statements) => self.block("Block", statements),
+            StatementKind::While(condition, body) => self.node("While", |p| {
+                p.expression(condition);
+                p.block("Body", body);
+            }),
+            StatementKind::For(name, start, end, body) => {
+                self.node(&format!("For {}", name), |p| {
+                    p.expression(start);
+                    p.expression(end);
+                    p.block("Body", body);
+                })
+            }
+            StatementKind::Break => self.line("Break"),
+            StatementKind::Continue => self.line("Continue"),
+            StatementKind::Function(decl) => self.function("Function", decl),
+            StatementKind::Return(None) => self.line("Return"),
+            StatementKind::Return(Some(value)) => self.node("Return", |p| p.expression(value)),
+        }
+    }
+
+    fn expression(&mut self, expr: &Expression) {
+        match &expr.kind {
+            ExpressionKind::Int(value) => self.line(&format!("Int {}", value)),
+            ExpressionKind::Float(value) => self.line(&format!("Float {:?}", value)),
+            ExpressionKind::Str(value) => self.line(&format!("Str {:?}", value)),
+            ExpressionKind::Interpolation(parts) => self.node("Interpolation", |p| {
+                for part in parts {
+                    p.expression(part);
+                }
+            }),
+            ExpressionKind::Bool(value) => self.line(&format!("Bool {}", value)),
+            ExpressionKind::Nil => self.line("Nil"),
+            ExpressionKind::Identifier(name, slot) => {
+                self.line(&format!("Identifier {}", variable(name, *slot)))
+            }
+            ExpressionKind::Assign(name, slot, value) => self
+                .node(&format!("Assign {}", variable(name, *slot)), |p| {
+                    p.expression(value)
+                }),
+            ExpressionKind::Negation(operand) => self.node("Negation", |p| p.expression(operand)),
+            ExpressionKind::Not(operand) => self.node("Not", |p| p.expression(operand)),
+            ExpressionKind::Binary(left, op, right) => {
+                self.node(&format!("Binary {:?}", op), |p| {
+                    p.expression(left);
+                    p.expression(right);
+                })
+            }
+            ExpressionKind::Logical(left, op, right) => {
+                self.node(&format!("Logical {:?}", op), |p| {
+                    p.expression(left);
+                    p.expression(right);
+                })
+            }
+            ExpressionKind::Call(callee, args) => self.node("Call", |p| {
+                p.expression(callee);
+                for arg in args {
+                    p.expression(arg);
+                }
+            }),
+            ExpressionKind::Index(value, index) => self.node("Index", |p| {
+                p.expression(value);
+                p.expression(index);
+            }),
+            ExpressionKind::Slice(value, start, end) => self.node("Slice", |p| {
+                p.expression(value);
+                match start {
+                    Some(start) => p.node("Start", |p| p.expression(start)),
+                    None => p.line("Start -"),
+                }
+                match end {
+                    Some(end) => p.node("End", |p| p.expression(end)),
+                    None => p.line("End -"),
+                }
+            }),
+            ExpressionKind::Lambda(decl) => self.function("Lambda", decl),
+        }
+    }
+}
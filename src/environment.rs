@@ -1,81 +1,543 @@
-use crate::parser::{Expression, Statement};
+use crate::builtins::BUILTINS;
+use crate::gc::{GcConfig, GcStats, Handle, Heap, Trace};
+use crate::output;
+use crate::parser::{Expression, ExpressionKind, Slot, Statement, StatementKind};
+use crate::runtime_error::{RuntimeError, RuntimeErrorKind};
+use crate::span::Span;
 use crate::token::TokenType;
+use crate::value::{Function, Value};
 use ecow::EcoString;
 use std::collections::HashMap;
+use std::rc::Rc;
 
 #[derive(Debug)]
+struct Binding {
+    value: Value,
+    mutable: bool,
+}
+
+/// One level of local scope, linked to the scope it is nested in.
+/// Variables are stored in declaration order and found by the `Slot` the `Resolver` gave them.
+/// Scopes live in the `Heap` and refer to each other by `ScopeId`.
+#[derive(Debug, Default)]
+pub struct Scope {
+    slots: Vec<Binding>,
+    /// `None` for a scope nested directly in the global one
+    parent: Option<ScopeId>,
+}
+
+impl Scope {
+    pub fn new(parent: Option<ScopeId>) -> Self {
+        Scope {
+            slots: Vec::new(),
+            parent,
+        }
+    }
+}
+
+/// Handle to a scope owned by the `Heap`
+pub type ScopeId = Handle<Scope>;
+
+impl Trace for Scope {
+    fn held_by(value: &Value) -> Vec<ScopeId> {
+        value.closure().into_iter().collect()
+    }
+
+    /// Its parent and the closures of the functions it holds
+    fn references(&self) -> Vec<ScopeId> {
+        let closures = self
+            .slots
+            .iter()
+            .filter_map(|binding| binding.value.closure());
+        self.parent.into_iter().chain(closures).collect()
+    }
+}
+
+/// Attach the span of the failing node to an error
+fn at<T>(result: Result<T, RuntimeErrorKind>, span: Span) -> Result<T, RuntimeError> {
+    result.map_err(|kind| RuntimeError::new(kind, span))
+}
+
+/// How a statement finished, loops and calls intercept everything but `Normal`
+#[derive(Debug, PartialEq)]
+pub enum Flow {
+    Normal,
+    Break,
+    Continue,
+    Return(Value),
+}
+
+/// Deepest call nesting before a script is stopped with a runtime error
+pub const MAX_CALL_DEPTH: usize = 256;
+
+/// Native stack the interpreter thread gets, enough for `MAX_CALL_DEPTH` nested calls even in
+/// a debug build, where each call goes through several large `execute` and `eval_expression`
+/// frames for the statements and expressions around it
+pub const STACK_SIZE: usize = 64 * 1024 * 1024;
+
 pub struct Environment {
-    pub variables: HashMap<EcoString, Expression>,
+    heap: Heap<Scope>,
+    /// Variables declared at the top level, looked up by name
+    globals: HashMap<EcoString, Binding>,
+    /// Innermost local scope, `None` at the top level
+    scope: Option<ScopeId>,
+    /// Scopes of the blocks and calls the current scope is nested in, innermost last
+    saved_scopes: Vec<Option<ScopeId>>,
+    /// Values held while evaluating a call, rooted until they are bound in the call's scope
+    temporaries: Vec<Value>,
+    call_depth: usize,
 }
 
 impl Environment {
-    pub fn new() -> Self {
+    pub fn with_gc(config: GcConfig) -> Self {
+        let globals = BUILTINS
+            .iter()
+            .map(|native| {
+                let binding = Binding {
+                    value: Value::Native(*native),
+                    mutable: true,
+                };
+                (native.name.into(), binding)
+            })
+            .collect();
+
         Environment {
-            variables: HashMap::new(),
+            heap: Heap::new(config),
+            globals,
+            scope: None,
+            saved_scopes: Vec::new(),
+            temporaries: Vec::new(),
+            call_depth: 0,
+        }
+    }
+
+    pub fn gc_stats(&self) -> GcStats {
+        self.heap.stats()
+    }
+
+    /// Local scope `depth` levels out from the current one
+    fn ancestor(&self, depth: usize) -> ScopeId {
+        let mut scope = self.scope.expect("local variable used at the top level");
+        for _ in 0..depth {
+            scope = self
+                .heap
+                .get(scope)
+                .parent
+                .expect("slot outside of the scope chain");
         }
+        scope
     }
 
-    pub fn get(&self, name: &str) -> Option<&Expression> {
-        self.variables.get(name)
+    /// Value of a local in its slot, or of a global by name.
+    /// A local may not be defined yet when its initializer calls a function reading it.
+    pub fn get(&self, name: &str, slot: Option<Slot>) -> Option<Value> {
+        let binding = match slot {
+            Some(slot) => self
+                .heap
+                .get(self.ancestor(slot.depth))
+                .slots
+                .get(slot.index),
+            None => self.globals.get(name),
+        };
+        binding.map(|binding| binding.value.clone())
     }
 
-    pub fn set(&mut self, name: EcoString, value: Expression) {
-        self.variables.insert(name, value);
+    /// Define a variable in the current scope. A local takes the next slot of its scope,
+    /// a global may be redeclared unless it is a constant.
+    pub fn define(
+        &mut self,
+        name: EcoString,
+        value: Value,
+        mutable: bool,
+    ) -> Result<(), RuntimeErrorKind> {
+        let binding = Binding { value, mutable };
+        match self.scope {
+            Some(scope) => self.heap.get_mut(scope).slots.push(binding),
+            None => {
+                if let Some(Binding { mutable: false, .. }) = self.globals.get(&name) {
+                    return Err(RuntimeErrorKind::RedeclareConstant(name));
+                }
+                self.globals.insert(name, binding);
+            }
+        }
+        Ok(())
+    }
+
+    /// Update a local in its slot, or a global by name
+    pub fn assign(
+        &mut self,
+        name: &str,
+        slot: Option<Slot>,
+        value: Value,
+    ) -> Result<(), RuntimeErrorKind> {
+        let binding = match slot {
+            Some(slot) => {
+                let scope = self.ancestor(slot.depth);
+                self.heap.get_mut(scope).slots.get_mut(slot.index)
+            }
+            None => self.globals.get_mut(name),
+        };
+
+        match binding {
+            Some(Binding { mutable: false, .. }) => {
+                Err(RuntimeErrorKind::AssignToConstant(name.into()))
+            }
+            Some(binding) => {
+                binding.value = value;
+                Ok(())
+            }
+            None => Err(RuntimeErrorKind::UndeclaredAssignment(name.into())),
+        }
+    }
+
+    /// Allocate a scope nested in `parent`, collecting garbage first when the heap asks for it
+    fn new_scope(&mut self, parent: Option<ScopeId>) -> ScopeId {
+        if self.heap.should_collect() {
+            let scopes = self
+                .saved_scopes
+                .iter()
+                .chain([&self.scope, &parent])
+                .flatten()
+                .copied();
+            let globals = self.globals.values().map(|binding| &binding.value);
+            self.heap
+                .collect(scopes, self.temporaries.iter().chain(globals));
+        }
+        self.heap.alloc(Scope::new(parent))
     }
 
     // Function to evaluate expressions based on the current environment
-    pub fn eval_expression(&mut self, expr: &Expression) -> Result<i64, String> {
-        match expr {
-            Expression::Int(value) => Ok(*value),
-            Expression::Float(value) => Ok(*value as i64),
-            Expression::Identifier(name) => {
-                // Extract the value from the environment to avoid borrowing conflicts.
-                let expr_to_eval = self.get(name).cloned();
-
-                if let Some(e) = expr_to_eval {
-                    // Evaluate the expression using the mutable reference.
-                    self.eval_expression(&e)
-                } else {
-                    Err(format!("Variable '{}' not found", name))
+    pub fn eval_expression(&mut self, expr: &Expression) -> Result<Value, RuntimeError> {
+        match &expr.kind {
+            ExpressionKind::Int(value) => Ok(Value::Int(*value)),
+            ExpressionKind::Float(value) => Ok(Value::Float(*value)),
+            ExpressionKind::Str(value) => Ok(Value::Str(value.clone())),
+            ExpressionKind::Interpolation(parts) => {
+                let mut result = EcoString::new();
+                for part in parts {
+                    let value = self.eval_expression(part)?;
+                    result.push_str(&value.to_string());
                 }
+                Ok(Value::Str(result))
+            }
+            ExpressionKind::Bool(value) => Ok(Value::Bool(*value)),
+            ExpressionKind::Nil => Ok(Value::Nil),
+            ExpressionKind::Identifier(name, slot) => self.get(name, *slot).ok_or_else(|| {
+                RuntimeError::new(RuntimeErrorKind::UndefinedVariable(name.clone()), expr.span)
+            }),
+            ExpressionKind::Assign(name, slot, value) => {
+                let value = self.eval_expression(value)?;
+                at(self.assign(name, *slot, value.clone()), expr.span)?;
+                Ok(value)
             }
-            Expression::Negation(expr) => {
-                let result = self.eval_expression(expr)?;
-                Ok(-result)
+            ExpressionKind::Negation(operand) => {
+                let result = self.eval_expression(operand)?;
+                at(result.negate(), expr.span)
             }
-            Expression::Binary(left, op, right) => {
+            ExpressionKind::Not(operand) => {
+                let result = self.eval_expression(operand)?;
+                Ok(Value::Bool(!result.is_truthy()))
+            }
+            ExpressionKind::Binary(left, op, right) => {
                 let left_value = self.eval_expression(left)?;
                 let right_value = self.eval_expression(right)?;
-                match op {
-                    TokenType::Plus => Ok(left_value + right_value),
-                    TokenType::Minus => Ok(left_value - right_value),
-                    TokenType::Star => Ok(left_value * right_value),
-                    TokenType::Slash => {
-                        if right_value != 0 {
-                            Ok(left_value / right_value)
-                        } else {
-                            Err("Division by zero".into())
-                        }
+                let result = match op {
+                    TokenType::Equal => Ok(Value::Bool(left_value == right_value)),
+                    TokenType::NotEqual => Ok(Value::Bool(left_value != right_value)),
+                    TokenType::Lt | TokenType::Gt | TokenType::LtEq | TokenType::GtEq => {
+                        left_value.compare(op, &right_value)
                     }
-                    _ => Err(format!("Unsupported operator: {:?}", op)),
+                    _ => left_value.arithmetic(op, &right_value),
+                };
+                at(result, expr.span)
+            }
+            ExpressionKind::Call(callee, args) => {
+                let base = self.temporaries.len();
+                let result = self.eval_call(callee, args, expr.span);
+                self.temporaries.truncate(base);
+                result
+            }
+            ExpressionKind::Index(value, index) => {
+                let value = self.eval_expression(value)?;
+                let index = self.eval_expression(index)?;
+                at(value.index(&index), expr.span)
+            }
+            ExpressionKind::Slice(value, start, end) => {
+                let value = self.eval_expression(value)?;
+                let start = start
+                    .as_ref()
+                    .map(|start| self.eval_expression(start))
+                    .transpose()?;
+                let end = end
+                    .as_ref()
+                    .map(|end| self.eval_expression(end))
+                    .transpose()?;
+                at(value.slice(start, end), expr.span)
+            }
+            ExpressionKind::Lambda(decl) => Ok(Value::Function(Rc::new(Function {
+                decl: decl.clone(),
+                closure: self.scope,
+            }))),
+            ExpressionKind::Logical(left, op, right) => {
+                let left_value = self.eval_expression(left)?.is_truthy();
+
+                // Short circuit: the right side only runs when it can change the result
+                match (op, left_value) {
+                    (TokenType::And, false) => Ok(Value::Bool(false)),
+                    (TokenType::Or, true) => Ok(Value::Bool(true)),
+                    _ => Ok(Value::Bool(self.eval_expression(right)?.is_truthy())),
                 }
             }
         }
     }
 
     // Function to execute a statement
-    pub fn execute(&mut self, stmt: &Statement) -> Result<(), String> {
-        match stmt {
-            Statement::Let(name, expr) => {
+    pub fn execute(&mut self, stmt: &Statement) -> Result<Flow, RuntimeError> {
+        match &stmt.kind {
+            StatementKind::Let(name, expr) => {
                 let value = self.eval_expression(expr)?;
-                self.set(name.clone(), Expression::Int(value));
-                Ok(())
+                at(self.define(name.clone(), value, true), stmt.span)?;
             }
-            Statement::Print(expr) => {
+            StatementKind::Const(name, expr) => {
                 let value = self.eval_expression(expr)?;
-                println!("{}", value);
-                Ok(())
+                at(self.define(name.clone(), value, false), stmt.span)?;
+            }
+            StatementKind::Expression(expr) => {
+                self.eval_expression(expr)?;
+            }
+            StatementKind::Print(expr) => {
+                let value = self.eval_expression(expr)?;
+                output::println(value);
             }
+            StatementKind::If(condition, then_branch, else_branch) => {
+                if self.eval_expression(condition)?.is_truthy() {
+                    return self.execute_block(then_branch);
+                } else if let Some(else_branch) = else_branch {
+                    return self.execute_block(else_branch);
+                }
+            }
+            StatementKind::Block(statements) => return self.execute_block(statements),
+            StatementKind::While(condition, body) => {
+                while self.eval_expression(condition)?.is_truthy() {
+                    match self.execute_block(body)? {
+                        Flow::Break => break,
+                        flow @ Flow::Return(_) => return Ok(flow),
+                        Flow::Normal | Flow::Continue => {}
+                    }
+                }
+            }
+            StatementKind::For(_, start, end, body) => {
+                let (start, end) = (self.eval_bound(start)?, self.eval_bound(end)?);
+
+                for i in start..end {
+                    // Each iteration gets its own binding of the loop variable, in slot 0
+                    let scope = self.new_scope(self.scope);
+                    self.heap.get_mut(scope).slots.push(Binding {
+                        value: Value::Int(i),
+                        mutable: true,
+                    });
+
+                    match self.execute_in_scope(body, scope)? {
+                        Flow::Break => break,
+                        flow @ Flow::Return(_) => return Ok(flow),
+                        Flow::Normal | Flow::Continue => {}
+                    }
+                }
+            }
+            StatementKind::Break => return Ok(Flow::Break),
+            StatementKind::Continue => return Ok(Flow::Continue),
+            StatementKind::Function(decl) => {
+                let function = Function {
+                    decl: decl.clone(),
+                    closure: self.scope,
+                };
+                let function = Value::Function(Rc::new(function));
+                at(self.define(decl.name.clone(), function, true), stmt.span)?;
+            }
+            StatementKind::Return(value) => {
+                let value = match value {
+                    Some(expr) => self.eval_expression(expr)?,
+                    None => Value::Nil,
+                };
+                return Ok(Flow::Return(value));
+            }
+        }
+
+        Ok(Flow::Normal)
+    }
+
+    /// Evaluate a bound of a `for` range, which must be an int
+    fn eval_bound(&mut self, expr: &Expression) -> Result<i64, RuntimeError> {
+        match self.eval_expression(expr)? {
+            Value::Int(value) => Ok(value),
+            other => Err(RuntimeError::new(
+                RuntimeErrorKind::TypeMismatch {
+                    expected: "int",
+                    found: other.type_name(),
+                },
+                expr.span,
+            )),
+        }
+    }
+
+    /// Evaluate the callee and arguments of a call and call it.
+    /// They are pushed to `temporaries`, the caller drops them once the call is done.
+    fn eval_call(
+        &mut self,
+        callee: &Expression,
+        args: &[Expression],
+        span: Span,
+    ) -> Result<Value, RuntimeError> {
+        let base = self.temporaries.len();
+        let callee = self.eval_expression(callee)?;
+        self.temporaries.push(callee.clone());
+        for arg in args {
+            let value = self.eval_expression(arg)?;
+            self.temporaries.push(value);
+        }
+        let args = self.temporaries[base + 1..].to_vec();
+
+        match callee {
+            Value::Function(function) => self.call_function(&function, args, span),
+            Value::Native(native) => {
+                if args.len() != native.arity {
+                    let kind = RuntimeErrorKind::ArityMismatch {
+                        name: native.name.into(),
+                        expected: native.arity,
+                        found: args.len(),
+                        definition: None,
+                    };
+                    return Err(RuntimeError::new(kind, span));
+                }
+                at((native.func)(&args), span)
+            }
+            other => Err(RuntimeError::new(
+                RuntimeErrorKind::TypeMismatch {
+                    expected: "function",
+                    found: other.type_name(),
+                },
+                span,
+            )),
+        }
+    }
+
+    /// Run the function body in a scope nested in its closure, with parameters bound to `args`.
+    /// `span` is the call expression, where errors about the call itself point.
+    fn call_function(
+        &mut self,
+        function: &Function,
+        args: Vec<Value>,
+        span: Span,
+    ) -> Result<Value, RuntimeError> {
+        if args.len() != function.arity() {
+            let kind = RuntimeErrorKind::ArityMismatch {
+                name: function.decl.name.clone(),
+                expected: function.arity(),
+                found: args.len(),
+                definition: Some(function.decl.span),
+            };
+            return Err(RuntimeError::new(kind, span));
         }
+
+        if self.call_depth == MAX_CALL_DEPTH {
+            return Err(RuntimeError::new(
+                RuntimeErrorKind::StackOverflow(MAX_CALL_DEPTH),
+                span,
+            ));
+        }
+
+        let scope = self.new_scope(function.closure);
+        // parameters take the first slots, in order
+        let slots = &mut self.heap.get_mut(scope).slots;
+        slots.extend(args.into_iter().map(|value| Binding {
+            value,
+            mutable: true,
+        }));
+
+        self.call_depth += 1;
+        let flow = self.execute_in_scope(&function.decl.body, scope);
+        self.call_depth -= 1;
+
+        match flow? {
+            Flow::Return(value) => Ok(value),
+            _ => Ok(Value::Nil),
+        }
+    }
+
+    /// Run statements in a fresh scope nested in the current one.
+    fn execute_block(&mut self, statements: &[Statement]) -> Result<Flow, RuntimeError> {
+        let inner = self.new_scope(self.scope);
+        self.execute_in_scope(statements, inner)
+    }
+
+    /// Run statements in `scope`, stopping at the first one that breaks or continues.
+    /// The outer scope is restored even when a statement fails.
+    fn execute_in_scope(
+        &mut self,
+        statements: &[Statement],
+        scope: ScopeId,
+    ) -> Result<Flow, RuntimeError> {
+        let outer = self.scope.replace(scope);
+        self.saved_scopes.push(outer);
+
+        let mut result = Ok(Flow::Normal);
+        for stmt in statements {
+            result = self.execute(stmt);
+            if !matches!(result, Ok(Flow::Normal)) {
+                break;
+            }
+        }
+
+        self.scope = self.saved_scopes.pop().unwrap();
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lexer::Lexer;
+    use crate::parser::Parser;
+    use crate::resolver::Resolver;
+
+    /// Program making `depth + 1` nested calls, each from inside an `if`, a `while`,
+    /// a `for` and a block
+    fn nested_calls(depth: usize) -> String {
+        format!(
+            "fn f(n) {{
+                 if n > 0 {{ while true {{ for i in 0..1 {{ {{ return f(n - 1) + 1; }} }} }} }}
+                 return 0;
+             }}
+             let result = f({});",
+            depth
+        )
+    }
+
+    /// Run `source` on a thread with the stack `main` gives the interpreter
+    fn run(source: String) -> Result<(), RuntimeErrorKind> {
+        let thread = std::thread::Builder::new().stack_size(STACK_SIZE);
+        let handle = thread.spawn(move || {
+            let mut lexer = Lexer::new(&source);
+            let mut statements = Parser::new(&mut lexer).parse_program().unwrap();
+            Resolver::new().resolve(&mut statements).unwrap();
+
+            let mut environment = Environment::with_gc(GcConfig::default());
+            for stmt in &statements {
+                environment.execute(stmt).map_err(|err| err.kind)?;
+            }
+            Ok(())
+        });
+        handle.unwrap().join().unwrap()
+    }
+
+    #[test]
+    fn deepest_calls_fit_on_the_stack() {
+        assert!(run(nested_calls(MAX_CALL_DEPTH - 1)).is_ok());
+    }
+
+    #[test]
+    fn deeper_calls_are_a_runtime_error() {
+        let result = run(nested_calls(MAX_CALL_DEPTH));
+        assert!(matches!(result, Err(RuntimeErrorKind::StackOverflow(_))));
     }
 }
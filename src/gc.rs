@@ -0,0 +1,193 @@
+use crate::value::Value;
+use std::fmt;
+use std::marker::PhantomData;
+
+/// Handle to an object owned by a `Heap<T>`
+pub struct Handle<T> {
+    index: usize,
+    kind: PhantomData<fn() -> T>,
+}
+
+impl<T> Handle<T> {
+    fn new(index: usize) -> Self {
+        Handle {
+            index,
+            kind: PhantomData,
+        }
+    }
+}
+
+// Derived impls would require `T` to implement them too
+impl<T> Clone for Handle<T> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<T> Copy for Handle<T> {}
+
+impl<T> PartialEq for Handle<T> {
+    fn eq(&self, other: &Self) -> bool {
+        self.index == other.index
+    }
+}
+
+impl<T> Eq for Handle<T> {}
+
+impl<T> fmt::Debug for Handle<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Handle({})", self.index)
+    }
+}
+
+/// Objects a `Heap` can own, found by following handles from the roots
+pub trait Trace: Sized {
+    /// Objects of this kind kept alive by `value`
+    fn held_by(value: &Value) -> Vec<Handle<Self>>;
+    /// Objects of this kind kept alive by this one
+    fn references(&self) -> Vec<Handle<Self>>;
+}
+
+/// When the heap collects garbage
+#[derive(Debug, Clone, Copy)]
+pub struct GcConfig {
+    /// Number of live objects that triggers the first collection
+    pub initial_threshold: usize,
+    /// After a collection, the next one happens once the live objects grew by this factor
+    pub growth_factor: f64,
+    /// Collect before every allocation, so an object that isn't rooted is freed right away
+    pub stress: bool,
+}
+
+impl Default for GcConfig {
+    fn default() -> Self {
+        GcConfig {
+            initial_threshold: 1024,
+            growth_factor: 2.0,
+            stress: false,
+        }
+    }
+}
+
+/// Counters of the heap since it was created
+#[derive(Debug, Clone, Copy, Default)]
+pub struct GcStats {
+    pub collections: usize,
+    pub allocated: usize,
+    pub freed: usize,
+    pub live: usize,
+    /// Most objects ever live at once
+    pub peak: usize,
+}
+
+impl fmt::Display for GcStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} collection(s), {} object(s) allocated, {} freed, {} live, {} at peak",
+            self.collections, self.allocated, self.freed, self.live, self.peak
+        )
+    }
+}
+
+/// Mark-and-sweep heap owning the objects closures capture: the local scopes of the tree
+/// walking interpreter and the upvalues of the VM. A closure can be held by what it captured,
+/// so reference counting alone would leak every recursive closure.
+pub struct Heap<T> {
+    /// `None` for free slots, reused by later allocations
+    objects: Vec<Option<T>>,
+    marked: Vec<bool>,
+    free: Vec<usize>,
+    /// Number of live objects that triggers the next collection
+    threshold: usize,
+    config: GcConfig,
+    stats: GcStats,
+}
+
+impl<T: Trace> Heap<T> {
+    pub fn new(config: GcConfig) -> Self {
+        Heap {
+            objects: Vec::new(),
+            marked: Vec::new(),
+            free: Vec::new(),
+            threshold: config.initial_threshold,
+            config,
+            stats: GcStats::default(),
+        }
+    }
+
+    pub fn stats(&self) -> GcStats {
+        self.stats
+    }
+
+    /// Whether the next allocation should be preceded by a collection
+    pub fn should_collect(&self) -> bool {
+        self.config.stress || self.stats.live >= self.threshold
+    }
+
+    pub fn alloc(&mut self, object: T) -> Handle<T> {
+        self.stats.allocated += 1;
+        self.stats.live += 1;
+        self.stats.peak = self.stats.peak.max(self.stats.live);
+
+        match self.free.pop() {
+            Some(index) => {
+                self.objects[index] = Some(object);
+                Handle::new(index)
+            }
+            None => {
+                self.objects.push(Some(object));
+                self.marked.push(false);
+                Handle::new(self.objects.len() - 1)
+            }
+        }
+    }
+
+    pub fn get(&self, handle: Handle<T>) -> &T {
+        self.objects[handle.index]
+            .as_ref()
+            .expect("object used after being collected")
+    }
+
+    pub fn get_mut(&mut self, handle: Handle<T>) -> &mut T {
+        self.objects[handle.index]
+            .as_mut()
+            .expect("object used after being collected")
+    }
+
+    /// Free every object that can't be reached from the `objects` in use
+    /// or from `values` held outside of the heap
+    pub fn collect<'a>(
+        &mut self,
+        objects: impl IntoIterator<Item = Handle<T>>,
+        values: impl IntoIterator<Item = &'a Value>,
+    ) {
+        let mut pending: Vec<Handle<T>> = objects.into_iter().collect();
+        for value in values {
+            pending.extend(T::held_by(value));
+        }
+
+        // mark
+        while let Some(handle) = pending.pop() {
+            if std::mem::replace(&mut self.marked[handle.index], true) {
+                continue;
+            }
+            pending.extend(self.get(handle).references());
+        }
+
+        // sweep
+        for (index, slot) in self.objects.iter_mut().enumerate() {
+            if std::mem::take(&mut self.marked[index]) || slot.is_none() {
+                continue;
+            }
+            *slot = None;
+            self.free.push(index);
+            self.stats.freed += 1;
+            self.stats.live -= 1;
+        }
+
+        self.stats.collections += 1;
+        let grown = self.stats.live as f64 * self.config.growth_factor;
+        self.threshold = (grown as usize).max(self.config.initial_threshold);
+    }
+}
@@ -1,25 +1,42 @@
 use ecow::EcoString;
 use std::iter::Peekable;
-use std::str::Chars;
+use std::str::CharIndices;
 
 use crate::lexer_error::LexerError;
-use crate::token::{Token, TokenType};
+use crate::span::Span;
+use crate::token::{StrPart, Token, TokenType};
 
 #[derive(Debug)]
 pub struct Lexer<'a> {
-    input: Peekable<Chars<'a>>,
+    input: Peekable<CharIndices<'a>>,
+    /// Byte offset of `current_char` in the whole source
     position: usize,
-    read_position: usize,
     current_char: Option<char>,
+    /// Where `input` starts in the whole source, non zero for embedded expressions
+    offset: usize,
+    end: usize,
+    /// Byte offset where the token being lexed starts
+    token_start: usize,
+    /// Where the last error returned by `get_next_token` was found
+    error_span: Span,
 }
 
 impl<'a> Lexer<'a> {
     pub fn new(input: &'a str) -> Self {
+        Self::with_offset(input, 0)
+    }
+
+    /// Lexer for a piece of a larger source starting at byte `offset`,
+    /// so spans still point into the whole source
+    pub fn with_offset(input: &'a str, offset: usize) -> Self {
         let mut lexer = Lexer {
-            input: input.chars().peekable(),
-            position: 0,
-            read_position: 0,
+            input: input.char_indices().peekable(),
+            position: offset,
             current_char: None,
+            offset,
+            end: offset + input.len(),
+            token_start: offset,
+            error_span: Span::default(),
         };
 
         lexer.advance();
@@ -27,17 +44,52 @@ impl<'a> Lexer<'a> {
     }
 
     pub fn advance(&mut self) {
-        self.current_char = self.input.next();
-        self.position += 1;
+        match self.input.next() {
+            Some((index, c)) => {
+                self.current_char = Some(c);
+                self.position = self.offset + index;
+            }
+            None => {
+                self.current_char = None;
+                self.position = self.end;
+            }
+        }
     }
 
     pub fn peek_char(&mut self) -> Option<&char> {
-        self.input.peek()
+        self.input.peek().map(|(_, c)| c)
+    }
+
+    /// Span of the code that made `get_next_token` fail
+    pub fn error_span(&self) -> Span {
+        self.error_span
+    }
+
+    fn fail<T>(&mut self, error: LexerError, span: Span) -> Result<T, LexerError> {
+        self.error_span = span;
+        Err(error)
+    }
+
+    /// Skip a character no token starts with, lexing resumes right after it
+    fn unexpected_char(&mut self, c: char) -> Result<Token, LexerError> {
+        self.advance();
+        self.fail(
+            LexerError::UnexpectedChar(c),
+            Span::new(self.token_start, self.position),
+        )
+    }
+
+    /// Token spanning from `token_start` up to the current position
+    fn token(&self, token: TokenType) -> Token {
+        Token {
+            token,
+            span: Span::new(self.token_start, self.position),
+        }
     }
 
     fn single_char_token(&mut self, token: TokenType) -> Result<Token, LexerError> {
         self.advance();
-        Ok(Token { token })
+        Ok(self.token(token))
     }
 
     fn double_char_token(
@@ -49,26 +101,24 @@ impl<'a> Lexer<'a> {
         if next_char == self.peek_char() {
             self.advance();
             self.advance();
-            return Ok(Token {
-                token: double_token,
-            });
+            Ok(self.token(double_token))
         } else {
             self.advance();
-            return Ok(Token {
-                token: single_token,
-            });
+            Ok(self.token(single_token))
         }
     }
 
     pub fn get_next_token(&mut self) -> Result<Token, LexerError> {
+        self.token_start = self.position;
+
         // If current_char is None, end of input reached, return EOF token
         if self.current_char.is_none() {
-            return Ok(Token {
-                token: TokenType::EOF,
-            });
+            return Ok(self.token(TokenType::EOF));
         }
 
         while let Some(c) = self.current_char {
+            self.token_start = self.position;
+
             match c {
                 ' ' | '\n' | '\t' | '\r' => {
                     self.advance();
@@ -79,14 +129,10 @@ impl<'a> Lexer<'a> {
                     if let Some('=') = self.peek_char() {
                         self.advance(); // consume '='
                         self.advance(); // consume next '='
-                        return Ok(Token {
-                            token: TokenType::Equal,
-                        });
+                        return Ok(self.token(TokenType::Equal));
                     } else {
                         self.advance();
-                        return Ok(Token {
-                            token: TokenType::Assign,
-                        });
+                        return Ok(self.token(TokenType::Assign));
                     }
                 }
 
@@ -98,6 +144,24 @@ impl<'a> Lexer<'a> {
                     );
                 }
 
+                '&' => {
+                    if let Some('&') = self.peek_char() {
+                        self.advance(); // consume '&'
+                        self.advance(); // consume next '&'
+                        return Ok(self.token(TokenType::And));
+                    }
+                    return self.unexpected_char(c);
+                }
+
+                '|' => {
+                    if let Some('|') = self.peek_char() {
+                        self.advance(); // consume '|'
+                        self.advance(); // consume next '|'
+                        return Ok(self.token(TokenType::Or));
+                    }
+                    return self.single_char_token(TokenType::Pipe);
+                }
+
                 '+' => {
                     return self.single_char_token(TokenType::Plus);
                 }
@@ -110,9 +174,19 @@ impl<'a> Lexer<'a> {
                     return self.single_char_token(TokenType::Star);
                 }
 
-                '/' => {
-                    return self.single_char_token(TokenType::Slash);
-                }
+                '/' => match self.peek_char() {
+                    Some('/') => {
+                        if let Some(doc) = self.skip_line_comment() {
+                            return Ok(self.token(TokenType::DocComment(doc)));
+                        }
+                        continue;
+                    }
+                    Some('*') => {
+                        self.skip_block_comment()?;
+                        continue;
+                    }
+                    _ => return self.single_char_token(TokenType::Slash),
+                },
 
                 '>' => {
                     return self.double_char_token(Some(&'='), TokenType::GtEq, TokenType::Gt);
@@ -126,11 +200,18 @@ impl<'a> Lexer<'a> {
                     return self.single_char_token(TokenType::Comma);
                 }
 
+                '.' => {
+                    if let Some('.') = self.peek_char() {
+                        self.advance(); // consume '.'
+                        self.advance(); // consume next '.'
+                        return Ok(self.token(TokenType::DotDot));
+                    }
+                    return self.unexpected_char(c);
+                }
+
                 ';' => {
                     self.advance();
-                    return Ok(Token {
-                        token: TokenType::Semicolon,
-                    });
+                    return Ok(self.token(TokenType::Semicolon));
                 }
 
                 '(' => {
@@ -149,44 +230,118 @@ impl<'a> Lexer<'a> {
                     return self.single_char_token(TokenType::RBrace);
                 }
 
+                '[' => {
+                    return self.single_char_token(TokenType::LBracket);
+                }
+
+                ']' => {
+                    return self.single_char_token(TokenType::RBracket);
+                }
+
+                '"' => {
+                    return self.lex_string();
+                }
+
                 '0'..='9' => {
                     let (num, is_float) = self.is_number();
-                    if is_float {
-                        let value = TokenType::Float(num.parse::<f64>().unwrap());
-                        return Ok(Token { token: value });
+                    // only digits were read, so parsing can just fail on values out of range
+                    let value = if is_float {
+                        num.parse::<f64>()
+                            .ok()
+                            .filter(|value| value.is_finite())
+                            .map(TokenType::Float)
                     } else {
-                        let value = num.parse::<i64>().unwrap();
-                        return Ok(Token {
-                            token: TokenType::Int(value),
-                        });
-                    }
+                        num.parse::<i64>().ok().map(TokenType::Int)
+                    };
+                    return match value {
+                        Some(value) => Ok(self.token(value)),
+                        None => self.fail(
+                            LexerError::NumberTooLarge(num),
+                            Span::new(self.token_start, self.position),
+                        ),
+                    };
                 }
 
                 'a'..='z' | 'A'..='Z' => {
                     return self.lex_identifier();
                 }
 
-                _ => return Err(LexerError::UnexpectedChar(c)),
+                _ => return self.unexpected_char(c),
                 //panic!("Unknown Character: {}", c);
             }
         }
         // Ok(Token {
         //     token: TokenType::EOF,
         // })
-        Ok(Token {
-            token: TokenType::EOF,
-        })
+        self.token_start = self.position;
+        Ok(self.token(TokenType::EOF))
         // Err(LexerError::EndOfInput)
     }
 
+    /// Skip a `//` comment up to the end of the line.
+    /// A `///` doc comment is kept, its text is returned without the leading slashes.
+    fn skip_line_comment(&mut self) -> Option<EcoString> {
+        self.advance(); // consume '/'
+        self.advance(); // consume next '/'
+
+        // `////...` is a plain comment, like a line of slashes used as a separator
+        let is_doc = self.current_char == Some('/') && self.peek_char() != Some(&'/');
+        if is_doc {
+            self.advance(); // consume third '/'
+        }
+
+        let mut text = EcoString::new();
+        while let Some(c) = self.current_char {
+            if c == '\n' {
+                break;
+            }
+            text.push(c);
+            self.advance();
+        }
+
+        is_doc.then(|| text.strip_prefix(' ').unwrap_or(&text).into())
+    }
+
+    /// Skip a `/* */` comment, which may contain nested block comments
+    fn skip_block_comment(&mut self) -> Result<(), LexerError> {
+        self.advance(); // consume '/'
+        self.advance(); // consume '*'
+
+        let mut depth = 1;
+        while let Some(c) = self.current_char {
+            match (c, self.peek_char()) {
+                ('/', Some('*')) => {
+                    self.advance();
+                    depth += 1;
+                }
+                ('*', Some('/')) => {
+                    self.advance();
+                    depth -= 1;
+                    if depth == 0 {
+                        self.advance(); // consume closing '/'
+                        return Ok(());
+                    }
+                }
+                _ => {}
+            }
+            self.advance();
+        }
+
+        // point at the opening '/*' rather than the rest of the input
+        let open = Span::new(self.token_start, self.token_start + 2);
+        self.fail(LexerError::UnterminatedComment, open)
+    }
+
     fn is_number(&mut self) -> (EcoString, bool) {
         let mut num_str = EcoString::new();
         let mut is_float = false;
 
         while let Some(c) = self.current_char {
-            if c.is_digit(10) {
+            if c.is_ascii_digit() {
                 num_str.push(c);
-            } else if c == '.' && !is_float {
+            } else if c == '.' && !is_float && self.peek_char().is_some_and(|c| c.is_ascii_digit())
+            {
+                // only a '.' followed by a digit makes a float, `0..10` is a range
                 is_float = true;
                 num_str.push(c);
             } else {
@@ -198,6 +353,108 @@ impl<'a> Lexer<'a> {
         (num_str, is_float)
     }
 
+    /// Lex a string literal, resolving `\n`, `\t`, `\r`, `\0`, `\\`, `\"`, `\{` and `\}` escapes.
+    /// `{expression}` segments turn it into an interpolated string.
+    /// An invalid escape is reported once the whole literal is skipped.
+    fn lex_string(&mut self) -> Result<Token, LexerError> {
+        self.advance(); // consume opening '"'
+
+        let mut parts = Vec::new();
+        let mut value = EcoString::new();
+        let mut invalid_escape = None;
+        loop {
+            match self.current_char {
+                Some('"') => {
+                    self.advance(); // consume closing '"'
+                    break;
+                }
+                Some('\\') => {
+                    let escape_start = self.position;
+                    self.advance(); // consume '\'
+                    let escaped = match self.current_char {
+                        Some('n') => '\n',
+                        Some('t') => '\t',
+                        Some('r') => '\r',
+                        Some('0') => '\0',
+                        Some(c @ ('\\' | '"' | '{' | '}')) => c,
+                        Some(c) => {
+                            let span = Span::new(escape_start, self.position + c.len_utf8());
+                            invalid_escape.get_or_insert((c, span));
+                            c
+                        }
+                        None => continue,
+                    };
+                    value.push(escaped);
+                    self.advance();
+                }
+                Some('{') => {
+                    if !value.is_empty() {
+                        parts.push(StrPart::Literal(std::mem::take(&mut value)));
+                    }
+                    let start = self.position + 1; // code starts after '{'
+                    parts.push(StrPart::Code(self.lex_interpolation()?, start));
+                }
+                Some(c) => {
+                    value.push(c);
+                    self.advance();
+                }
+                None => {
+                    let span = Span::new(self.token_start, self.position);
+                    return self.fail(LexerError::UnterminatedString, span);
+                }
+            }
+        }
+
+        if let Some((c, span)) = invalid_escape {
+            return self.fail(LexerError::InvalidEscape(c), span);
+        }
+
+        if parts.is_empty() {
+            return Ok(self.token(TokenType::Str(value)));
+        }
+
+        if !value.is_empty() {
+            parts.push(StrPart::Literal(value));
+        }
+        Ok(self.token(TokenType::InterpolatedStr(parts)))
+    }
+
+    /// Collect the source of an embedded `{expression}` up to its matching `}`.
+    /// Braces and string literals inside the expression are skipped over as a whole.
+    fn lex_interpolation(&mut self) -> Result<EcoString, LexerError> {
+        let open = self.position;
+        self.advance(); // consume '{'
+
+        let mut code = EcoString::new();
+        let mut depth = 0;
+        let mut in_string = false;
+        loop {
+            let Some(c) = self.current_char else {
+                let span = Span::new(open, self.position);
+                return self.fail(LexerError::UnterminatedInterpolation, span);
+            };
+            self.advance();
+
+            match c {
+                '}' if !in_string && depth == 0 => return Ok(code),
+                '{' if !in_string => depth += 1,
+                '}' if !in_string => depth -= 1,
+                '"' => in_string = !in_string,
+                '\\' if in_string => {
+                    // keep the escaped character so an escaped quote doesn't end the string
+                    code.push(c);
+                    if let Some(escaped) = self.current_char {
+                        code.push(escaped);
+                        self.advance();
+                    }
+                    continue;
+                }
+                _ => {}
+            }
+            code.push(c);
+        }
+    }
+
     fn lex_identifier(&mut self) -> Result<Token, LexerError> {
         let mut identifier = EcoString::new();
         while let Some(c) = self.current_char {
@@ -212,16 +469,23 @@ impl<'a> Lexer<'a> {
         // Check if it matches a keyword or treat as generic identifier
         let token_type = match identifier.as_str() {
             "let" => TokenType::Let,
+            "const" => TokenType::Const,
             "if" => TokenType::If,
             "print" => TokenType::Print,
             "else" => TokenType::Else,
+            "while" => TokenType::While,
+            "for" => TokenType::For,
+            "in" => TokenType::In,
+            "break" => TokenType::Break,
+            "continue" => TokenType::Continue,
             "true" => TokenType::True,
             "false" => TokenType::False,
+            "nil" => TokenType::Nil,
             "fn" => TokenType::Function,
             "return" => TokenType::Return,
             _ => TokenType::Identifier(identifier.clone()),
         };
 
-        Ok(Token { token: token_type })
+        Ok(self.token(token_type))
     }
 }
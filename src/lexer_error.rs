@@ -1,16 +1,18 @@
-use std::fmt;
+use ecow::EcoString;
+use thiserror::Error;
 
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq, Error)]
 pub enum LexerError {
+    #[error("Unexpected character: {0}")]
     UnexpectedChar(char),
-    EndOfInput,
-}
-
-impl fmt::Display for LexerError {
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        match self {
-            LexerError::UnexpectedChar(c) => write!(f, "Unexpected character: {}", c),
-            LexerError::EndOfInput => write!(f, "End of input"),
-        }
-    }
+    #[error("Unterminated string literal")]
+    UnterminatedString,
+    #[error("Invalid escape sequence: \\{0}")]
+    InvalidEscape(char),
+    #[error("Unterminated '{{' in string interpolation")]
+    UnterminatedInterpolation,
+    #[error("Unterminated block comment")]
+    UnterminatedComment,
+    #[error("Number literal too large: {0}")]
+    NumberTooLarge(EcoString),
 }
@@ -1,56 +1,307 @@
 //mod ast;
+mod builtins;
+mod chunk;
+mod cli;
+mod compile_error;
+mod compiler;
+mod diagnostic;
+mod disassembler;
+mod dump;
 mod environment;
+mod gc;
 mod lexer;
 mod lexer_error;
+mod output;
 mod parser;
+mod parser_error;
+mod repl;
+mod resolve_error;
+mod resolver;
+mod runtime_error;
+mod rxc;
+mod rxc_error;
+mod span;
 mod token;
+mod value;
+mod vm;
 
-use crate::environment::Environment;
+use crate::chunk::FunctionProto;
+use crate::cli::{Cli, Command, Dump, Engine, Input, USAGE};
+use crate::compiler::Compiler;
+use crate::diagnostic::Diagnostic;
+use crate::environment::{Environment, STACK_SIZE};
 use crate::lexer::Lexer;
-use crate::parser::Parser;
+use crate::parser::{Parser, Statement};
+use crate::resolver::Resolver;
+use crate::span::SourceMap;
+use crate::vm::Vm;
+use std::io::Read;
+use std::path::Path;
+use std::process::ExitCode;
+use std::rc::Rc;
 
-fn main() {
-    // Sample input
-    let input = "let x = 5 + 3; let y = x * 2; print(y + 5);";
+// Exit codes, following sysexits.h
+const EXIT_USAGE: u8 = 64;
+const EXIT_SYNTAX: u8 = 65;
+const EXIT_NO_INPUT: u8 = 66;
+const EXIT_RUNTIME: u8 = 70;
+const EXIT_CANT_CREATE: u8 = 73;
+const EXIT_IO_ERROR: u8 = 74;
 
-    // Create lexer and parser
-    let mut lexer = Lexer::new(input);
-    let mut parser = Parser::new(&mut lexer);
+/// A program to run, from source code or loaded from a `.rxc` file
+struct Program {
+    name: String,
+    source: String,
+    /// Set for precompiled programs, which always run on the VM
+    script: Option<Rc<FunctionProto>>,
+}
 
-    // Create an environment to store variables
-    let mut environment = Environment::new();
-
-    // Parse and execute statements
-    while let Some(stmt) = parser.parse_statement() {
-        match stmt {
-            Ok(statement) => {
-                // Execute each statement
-                if let Err(e) = environment.execute(&statement) {
-                    eprintln!("Error executing statement: {}", e);
-                }
-            }
-            Err(e) => {
-                eprintln!("Error parsing statement: {}", e);
-                break;
+impl Program {
+    fn source(name: &str, source: String) -> Self {
+        Program {
+            name: name.to_string(),
+            source,
+            script: None,
+        }
+    }
+}
+
+fn main() -> ExitCode {
+    // the tree walker recurses on the native stack for every call, see `STACK_SIZE`
+    std::thread::Builder::new()
+        .stack_size(STACK_SIZE)
+        .spawn(relox)
+        .expect("cannot start the interpreter thread")
+        .join()
+        .unwrap_or_else(|panic| std::panic::resume_unwind(panic))
+}
+
+fn relox() -> ExitCode {
+    diagnostic::init_color();
+
+    let mut cli = match Command::parse(std::env::args().skip(1)) {
+        Ok(Command::Run(cli)) => cli,
+        Ok(Command::Compile { input, output }) => {
+            let output = output.unwrap_or_else(|| input.with_extension("rxc"));
+            return compile_file(&input, &output);
+        }
+        Ok(Command::Repl { gc, gc_stats }) => return repl::run(gc, gc_stats),
+        Ok(Command::Help) => {
+            output::println(USAGE);
+            return ExitCode::SUCCESS;
+        }
+        Err(err) => {
+            eprintln!("error: {}\n\n{}", err, USAGE);
+            return ExitCode::from(EXIT_USAGE);
+        }
+    };
+
+    let program = match &cli.input {
+        Input::File(path) => match read_file(path) {
+            Ok(program) => program,
+            Err(code) => return code,
+        },
+        Input::Eval(code) => Program::source("<eval>", code.clone()),
+        Input::Stdin => {
+            let mut source = String::new();
+            if let Err(err) = std::io::stdin().read_to_string(&mut source) {
+                eprintln!("error: Cannot read stdin: {}", err);
+                return ExitCode::from(EXIT_NO_INPUT);
             }
+            Program::source("<stdin>", source)
+        }
+    };
+
+    if cli.dump.any() {
+        return dump(&program, &cli.dump);
+    }
+
+    builtins::set_args(std::mem::take(&mut cli.args));
+    run(program, &cli)
+}
+
+/// Read a script, or a precompiled program when the file starts like a `.rxc` file
+fn read_file(path: &Path) -> Result<Program, ExitCode> {
+    let cannot_read = |err: &dyn std::fmt::Display| {
+        eprintln!("error: Cannot read '{}': {}", path.display(), err);
+        ExitCode::from(EXIT_NO_INPUT)
+    };
+    let bytes = std::fs::read(path).map_err(|err| cannot_read(&err))?;
+
+    if !rxc::is_bytecode(&bytes) {
+        let source = String::from_utf8(bytes).map_err(|err| cannot_read(&err))?;
+        return Ok(Program::source(&path.display().to_string(), source));
+    }
+
+    let compiled = rxc::decode(&bytes).map_err(|err| {
+        eprintln!("error: Cannot load '{}': {}", path.display(), err);
+        ExitCode::from(EXIT_SYNTAX)
+    })?;
+
+    // Still runs the compiled program, the source may have been edited on purpose
+    if compiled.source_changed() {
+        eprintln!(
+            "warning: '{}' changed since '{}' was compiled, recompile it with 'relox compile'",
+            compiled.path,
+            path.display()
+        );
+    }
+
+    Ok(Program {
+        name: compiled.name,
+        source: compiled.source,
+        script: Some(compiled.script),
+    })
+}
+
+/// Compile a script to a `.rxc` file that runs without lexing and parsing it again
+fn compile_file(input: &Path, output: &Path) -> ExitCode {
+    let source = match std::fs::read_to_string(input) {
+        Ok(source) => source,
+        Err(err) => {
+            eprintln!("error: Cannot read '{}': {}", input.display(), err);
+            return ExitCode::from(EXIT_NO_INPUT);
         }
+    };
+
+    let name = input.display().to_string();
+    let source_map = SourceMap::new(&name, &source);
+    let script = match parse(&source, &source_map)
+        .and_then(|statements| compile(&statements, &source_map))
+    {
+        Ok(script) => script,
+        Err(code) => return code,
+    };
+
+    // resolved now, the program may be run from another directory
+    let path = std::fs::canonicalize(input)
+        .map(|path| path.display().to_string())
+        .unwrap_or_default();
+    let bytes = rxc::encode(&script, &name, &path, &source);
+    if let Err(err) = std::fs::write(output, bytes) {
+        eprintln!("error: Cannot write '{}': {}", output.display(), err);
+        return ExitCode::from(EXIT_CANT_CREATE);
     }
+
+    ExitCode::SUCCESS
 }
-/*
 
-fn main() {
-    let input = "let x = 10 - 45; let x = y; print (x); print y+ 7;";
-    let mut lexer = Lexer::new(input);
+/// Parse and resolve the whole program, reporting every error
+fn parse(source: &str, source_map: &SourceMap) -> Result<Vec<Statement>, ExitCode> {
+    let mut lexer = Lexer::new(source);
     let mut parser = Parser::new(&mut lexer);
 
-    // Continue parsing until parse_statement returns None
-    while let Some(result) = parser.parse_statement() {
-        match result {
-            Ok(stmt) => println!("{:?}", stmt),
-            Err(err) => {
-                eprintln!("Error parsing statement: {}", err);
-                break;
-            }
+    let mut statements = parser.parse_program().map_err(|errors| {
+        let count = errors.len();
+        for e in errors {
+            Diagnostic::from(e).emit(source_map);
         }
+        eprintln!("aborting due to {} syntax error(s)", count);
+        ExitCode::from(EXIT_SYNTAX)
+    })?;
+
+    Resolver::new().resolve(&mut statements).map_err(|errors| {
+        let count = errors.len();
+        for e in errors {
+            Diagnostic::from(e).emit(source_map);
+        }
+        eprintln!("aborting due to {} error(s)", count);
+        ExitCode::from(EXIT_SYNTAX)
+    })?;
+
+    Ok(statements)
+}
+
+fn compile(
+    statements: &[Statement],
+    source_map: &SourceMap,
+) -> Result<Rc<FunctionProto>, ExitCode> {
+    Compiler::new().compile(statements).map_err(|e| {
+        Diagnostic::from(e).emit(source_map);
+        ExitCode::from(EXIT_SYNTAX)
+    })
+}
+
+/// Print the requested stages of the pipeline for a program without running it
+fn dump(program: &Program, dump: &Dump) -> ExitCode {
+    let source_map = SourceMap::new(&program.name, &program.source);
+
+    if dump.tokens && !dump::tokens(&program.source, &source_map) {
+        return ExitCode::from(EXIT_SYNTAX);
     }
-}*/
+    if !dump.ast && !dump.bytecode {
+        return ExitCode::SUCCESS;
+    }
+
+    let statements = match parse(&program.source, &source_map) {
+        Ok(statements) => statements,
+        Err(code) => return code,
+    };
+    if dump.ast {
+        output::print(dump::ast(&statements));
+    }
+    if dump.bytecode {
+        let script = match &program.script {
+            Some(script) => script.clone(),
+            None => match compile(&statements, &source_map) {
+                Ok(script) => script,
+                Err(code) => return code,
+            },
+        };
+        output::print(disassembler::disassemble(&script, &source_map));
+    }
+
+    ExitCode::SUCCESS
+}
+
+/// Parse and run a whole program, reporting errors against its name
+fn run(program: Program, cli: &Cli) -> ExitCode {
+    let source_map = SourceMap::new(&program.name, &program.source);
+
+    if let Some(script) = program.script {
+        return run_vm(script, &source_map, cli);
+    }
+
+    // Parse the whole program first, nothing runs if it has syntax errors
+    let statements = match parse(&program.source, &source_map) {
+        Ok(statements) => statements,
+        Err(code) => return code,
+    };
+
+    if cli.engine == Engine::Vm {
+        return match compile(&statements, &source_map) {
+            Ok(script) => run_vm(script, &source_map, cli),
+            Err(code) => code,
+        };
+    }
+
+    let mut environment = Environment::with_gc(cli.gc);
+    let mut code = ExitCode::SUCCESS;
+    for statement in &statements {
+        // A runtime error stops the program
+        if let Err(e) = environment.execute(statement) {
+            Diagnostic::from(e).emit(&source_map);
+            code = ExitCode::from(EXIT_RUNTIME);
+            break;
+        }
+    }
+
+    if cli.gc_stats {
+        eprintln!("gc: {}", environment.gc_stats());
+    }
+    code
+}
+
+fn run_vm(script: Rc<FunctionProto>, source_map: &SourceMap, cli: &Cli) -> ExitCode {
+    let mut vm = Vm::new(cli.gc);
+    let mut code = ExitCode::SUCCESS;
+    if let Err(e) = vm.run(script) {
+        Diagnostic::from(e).emit(source_map);
+        code = ExitCode::from(EXIT_RUNTIME);
+    }
+
+    if cli.gc_stats {
+        eprintln!("gc: {}", vm.gc_stats());
+    }
+    code
+}
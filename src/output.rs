@@ -0,0 +1,19 @@
+use std::fmt::Display;
+use std::io::{ErrorKind, Write};
+
+/// Write `text` to stdout. Unlike `print!`, failing to write doesn't panic:
+/// when the reader went away, like `head` at the end of a pipeline, the program just ends.
+pub fn print(text: impl Display) {
+    if let Err(err) = write!(std::io::stdout().lock(), "{}", text) {
+        if err.kind() == ErrorKind::BrokenPipe {
+            std::process::exit(0);
+        }
+        eprintln!("error: Cannot write to stdout: {}", err);
+        std::process::exit(crate::EXIT_IO_ERROR.into());
+    }
+}
+
+/// `print` followed by a newline
+pub fn println(text: impl Display) {
+    print(format_args!("{}\n", text));
+}
@@ -1,27 +1,117 @@
-use std::ops::RemAssign;
-
 use crate::lexer::Lexer;
-use crate::token::{Token, TokenType};
+use crate::parser_error::{ParseError, ParseErrorKind};
+use crate::span::Span;
+use crate::token::{StrPart, Token, TokenType};
 use ecow::EcoString;
+use std::rc::Rc;
+
+#[derive(Debug, Clone)]
+pub struct Expression {
+    pub kind: ExpressionKind,
+    pub span: Span,
+}
 
 #[derive(Debug, Clone)]
-pub enum Expression {
+pub enum ExpressionKind {
     Int(i64),
     Float(f64),
-    Identifier(EcoString),
+    Str(EcoString),
+    /// `"total: {x + y}"`, literal segments and embedded expressions in order
+    Interpolation(Vec<Expression>),
+    Bool(bool),
+    Nil,
+    /// Variable read, with its slot once the `Resolver` found it's a local
+    Identifier(EcoString, Option<Slot>),
+    /// `name = value`, evaluates to the assigned value
+    Assign(EcoString, Option<Slot>, Box<Expression>),
     Negation(Box<Expression>),
+    Not(Box<Expression>),
     Binary(Box<Expression>, TokenType, Box<Expression>),
+    /// Callee and arguments
+    Call(Box<Expression>, Vec<Expression>),
+    /// `value[index]`
+    Index(Box<Expression>, Box<Expression>),
+    /// `value[start..end]`, either bound may be left out
+    Slice(
+        Box<Expression>,
+        Option<Box<Expression>>,
+        Option<Box<Expression>>,
+    ),
+    /// Anonymous function, evaluates to a closure over the current scope
+    Lambda(Rc<FunctionDecl>),
+    /// `&&` and `||`, kept apart from `Binary` because the right side is evaluated lazily
+    Logical(Box<Expression>, TokenType, Box<Expression>),
+}
+
+impl Expression {
+    pub fn new(kind: ExpressionKind, span: Span) -> Self {
+        Expression { kind, span }
+    }
+}
+
+/// Where the `Resolver` found a local variable: in the scope `depth` levels out
+/// from the one it's used in, at index `index` in declaration order.
+/// Variables without a slot are globals, looked up by name.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Slot {
+    pub depth: usize,
+    pub index: usize,
 }
 
 #[derive(Debug, Clone)]
-pub enum Statement {
+pub struct Statement {
+    pub kind: StatementKind,
+    pub span: Span,
+}
+
+#[derive(Debug, Clone)]
+pub enum StatementKind {
     Let(EcoString, Expression),
+    /// Like `Let`, but the binding can never be assigned to again
+    Const(EcoString, Expression),
     Print(Expression),
+    Expression(Expression),
+    /// Condition, then block and optional else block.
+    /// `else if` is an else block holding a single nested `If`.
+    If(Expression, Vec<Statement>, Option<Vec<Statement>>),
+    Block(Vec<Statement>),
+    While(Expression, Vec<Statement>),
+    /// `for name in start..end { }`, iterating from `start` up to but excluding `end`
+    For(EcoString, Expression, Expression, Vec<Statement>),
+    Break,
+    Continue,
+    Function(Rc<FunctionDecl>),
+    /// `return;` returns nil
+    Return(Option<Expression>),
+}
+
+impl Statement {
+    pub fn new(kind: StatementKind, span: Span) -> Self {
+        Statement { kind, span }
+    }
+}
+
+/// Declaration of a function, shared by every value created from it
+#[derive(Debug, Clone)]
+pub struct FunctionDecl {
+    pub name: EcoString,
+    pub params: Vec<EcoString>,
+    pub body: Vec<Statement>,
+    /// Signature up to the closing `)` or `|` of the parameter list
+    pub span: Span,
 }
 
 pub struct Parser<'a> {
     lexer: &'a mut Lexer<'a>,
     current_token: Token,
+    /// End of the last consumed token, where the node being parsed ends
+    previous_end: usize,
+    /// Number of loops enclosing the current statement, `break` is only valid inside one
+    loop_depth: usize,
+    /// Syntax errors recovered from so far, lexer errors included
+    errors: Vec<ParseError>,
+    /// Number of lexer errors in `errors`
+    lexer_errors: usize,
 }
 
 impl<'a> Parser<'a> {
@@ -30,53 +120,205 @@ impl<'a> Parser<'a> {
             lexer,
             current_token: Token {
                 token: TokenType::EOF,
+                span: Span::default(),
             },
+            previous_end: 0,
+            loop_depth: 0,
+            errors: Vec::new(),
+            lexer_errors: 0,
         };
 
         parser.advance();
         parser
     }
 
-    /// Advance to next token after successfuly consume one
-    /// if next token not found or end of file reach set token to "EOF"
+    /// Advance to next token after successfuly consume one.
+    /// Lexer errors are recorded and the input they cover is skipped.
+    /// Doc comments carry no meaning for the program and are skipped.
     fn advance(&mut self) {
-        self.current_token = self.lexer.get_next_token().unwrap_or_else(|_| Token {
-            token: TokenType::EOF, // If error, set token to EOF
-        });
+        self.previous_end = self.current_token.span.end;
+
+        loop {
+            match self.lexer.get_next_token() {
+                Ok(Token {
+                    token: TokenType::DocComment(_),
+                    ..
+                }) => {}
+                Ok(token) => {
+                    self.current_token = token;
+                    break;
+                }
+                Err(err) => {
+                    let span = self.lexer.error_span();
+                    self.errors.push(ParseError::new(err.into(), span));
+                    self.lexer_errors += 1;
+                }
+            }
+        }
     }
 
-    /// This is function which parse keyword and lexer into parser
-    /// !TODO:
-    ///    [1] MAke more robust parser
-    ///    [2] Handle more types of Error while parsing
-    pub fn parse_statement(&mut self) -> Option<Result<Statement, String>> {
-        // Check if the current token is EOF before parsing
-        if self.current_token.token == TokenType::EOF {
-            return None;
+    /// Error pointing at the current token
+    fn error(&self, kind: ParseErrorKind) -> ParseError {
+        ParseError::new(kind, self.current_token.span)
+    }
+
+    /// Error for a token other than the `expected` one at the current position
+    fn unexpected(&self, expected: &str) -> ParseError {
+        self.error(ParseErrorKind::UnexpectedToken {
+            expected: expected.into(),
+            found: self.current_token.token.clone(),
+        })
+    }
+
+    /// Record an error the parser can carry on from without skipping any input
+    fn report(&mut self, err: ParseError) {
+        self.errors.push(err);
+    }
+
+    /// Span from `start` up to the end of the last consumed token
+    fn span_from(&self, start: Span) -> Span {
+        Span::new(start.start, self.previous_end.max(start.start))
+    }
+
+    /// Parse the whole input. After a syntax error parsing resumes at the next statement,
+    /// so every error in the input is returned, ordered by position.
+    pub fn parse_program(&mut self) -> Result<Vec<Statement>, Vec<ParseError>> {
+        let mut statements = Vec::new();
+        while self.current_token.token != TokenType::EOF {
+            self.parse_recovering(&mut statements);
         }
 
-        let stmt = match &self.current_token.token {
-            TokenType::Let => self.parse_let(),
-            TokenType::Print => self.parse_print(),
-            _ => return Some(Err(format!("Unexpected token: {:?}", self.current_token))),
-        };
+        if self.errors.is_empty() {
+            Ok(statements)
+        } else {
+            let mut errors = std::mem::take(&mut self.errors);
+            errors.sort_by_key(|err| err.span.start);
+            Err(errors)
+        }
+    }
+
+    /// Parse a statement into `statements`, or record its error and skip past it
+    fn parse_recovering(&mut self, statements: &mut Vec<Statement>) {
+        let start = self.current_token.span;
+        let lexer_errors = self.lexer_errors;
 
-        // Advance to the next token if successfully parsed a statement
-        if stmt.is_ok() {
+        match self.statement() {
+            Ok(stmt) => statements.push(stmt),
+            Err(err) => {
+                // Skipped invalid input usually breaks the statement around it,
+                // the lexer error is the one worth reporting then
+                if self.lexer_errors == lexer_errors {
+                    self.errors.push(err);
+                }
+                self.synchronize(start);
+            }
+        }
+    }
+
+    /// Panic mode: skip tokens up to the end of the statement that failed to parse,
+    /// that is past its `;` or up to a `}` or the keyword starting the next statement.
+    fn synchronize(&mut self, start: Span) {
+        // Always make progress, even when the first token of the statement is the culprit
+        if self.current_token.span == start {
+            let first = self.current_token.token.clone();
             self.advance();
+            if first == TokenType::Semicolon {
+                return;
+            }
         }
 
-        Some(stmt)
+        loop {
+            match self.current_token.token {
+                TokenType::Semicolon => {
+                    self.advance();
+                    return;
+                }
+                TokenType::EOF
+                | TokenType::RBrace
+                | TokenType::Let
+                | TokenType::Const
+                | TokenType::Print
+                | TokenType::If
+                | TokenType::While
+                | TokenType::For
+                | TokenType::Break
+                | TokenType::Continue
+                | TokenType::Function
+                | TokenType::Return => return,
+                // a body belongs to the broken statement, its contents aren't statements of their own
+                TokenType::LBrace => self.skip_braces(),
+                _ => self.advance(),
+            }
+        }
+    }
+
+    /// Skip a `{ }` pair and everything nested in it
+    fn skip_braces(&mut self) {
+        let mut depth = 0;
+        loop {
+            match self.current_token.token {
+                TokenType::LBrace => depth += 1,
+                TokenType::RBrace => depth -= 1,
+                TokenType::EOF => return,
+                _ => {}
+            }
+            self.advance();
+
+            if depth == 0 {
+                return;
+            }
+        }
+    }
+
+    fn statement(&mut self) -> Result<Statement, ParseError> {
+        let start = self.current_token.span;
+
+        let kind = match &self.current_token.token {
+            TokenType::Let | TokenType::Const => self.parse_let(),
+            TokenType::Print => self.parse_print(),
+            TokenType::If => self.parse_if(),
+            TokenType::LBrace => Ok(StatementKind::Block(self.parse_block()?)),
+            TokenType::While => self.parse_while(),
+            TokenType::For => self.parse_for(),
+            TokenType::Break | TokenType::Continue => self.parse_loop_jump(),
+            TokenType::Function => self.parse_function(),
+            TokenType::Return => self.parse_return(),
+            _ => self.parse_expression_statement(),
+        }?;
+
+        Ok(Statement::new(kind, self.span_from(start)))
+    }
+
+    /// Consume the `;` ending a simple statement.
+    /// The last statement of the input may leave it out.
+    fn consume_semicolon(&mut self) -> Result<(), ParseError> {
+        match self.current_token.token {
+            TokenType::Semicolon => {
+                self.advance();
+                Ok(())
+            }
+            TokenType::EOF => Ok(()),
+            _ => Err(ParseError::new(
+                ParseErrorKind::MissingSemicolon {
+                    found: self.current_token.token.clone(),
+                    found_span: self.current_token.span,
+                },
+                Span::new(self.previous_end, self.previous_end),
+            )),
+        }
     }
 
-    /// Parse the "let" statement
+    /// Parse the "let" and "const" statements
     /// E.g.
     /// ```
     ///  let x = 45;
-    ///  let y = x + 12;  
+    ///  let y = x + 12;
+    ///  const LIMIT = 100;
     ///```
-    fn parse_let(&mut self) -> Result<Statement, String> {
-        // consume the "let" keyword and move forward
+    fn parse_let(&mut self) -> Result<StatementKind, ParseError> {
+        let is_const = self.current_token.token == TokenType::Const;
+
+        // consume the "let" or "const" keyword and move forward
         self.advance();
 
         // collect variable and make clone of it.
@@ -88,91 +330,574 @@ impl<'a> Parser<'a> {
             if let TokenType::Assign = self.current_token.token {
                 self.advance();
                 let expr = self.parse_expression()?;
-                Ok(Statement::Let(name.clone(), expr))
+                self.consume_semicolon()?;
+                if is_const {
+                    Ok(StatementKind::Const(name, expr))
+                } else {
+                    Ok(StatementKind::Let(name, expr))
+                }
             } else if let TokenType::EOF = self.current_token.token {
                 // If we reached EOF, stop further parsing
-                Err("Unexpected EOF while parsing let statement".into())
+                Err(self.error(ParseErrorKind::UnexpectedEof {
+                    context: "let statement",
+                }))
             } else {
-                Err(format!("Expected '=', found {:?}", self.current_token))
+                Err(self.unexpected("'='"))
             }
         } else if let TokenType::EOF = self.current_token.token {
             // Handle EOF at the point of expecting an identifier
-            Err("Unexpected EOF while parsing let statement".into())
+            Err(self.error(ParseErrorKind::UnexpectedEof {
+                context: "let statement",
+            }))
         } else {
-            Err(format!(
-                "Expected identifier, found {:?}",
-                self.current_token
-            ))
+            Err(self.unexpected("identifier"))
         }
     }
 
-    fn parse_print(&mut self) -> Result<Statement, String> {
+    fn parse_print(&mut self) -> Result<StatementKind, ParseError> {
         self.advance(); // consume 'print'
 
-        if let TokenType::LParen = self.current_token.token {
+        // `print(x)` is just a parenthesized expression, so `print (x) * 2` works too
+        let expr = self.parse_expression()?;
+        self.consume_semicolon()?;
+
+        Ok(StatementKind::Print(expr))
+    }
+
+    /// Parse the "if" statement
+    /// E.g.
+    /// ```
+    ///  if x > 10 {
+    ///      print x;
+    ///  } else if x > 5 {
+    ///      print 5;
+    ///  } else {
+    ///      print 0;
+    ///  }
+    ///```
+    fn parse_if(&mut self) -> Result<StatementKind, ParseError> {
+        self.advance(); // consume 'if'
+
+        let condition = self.parse_expression()?;
+        let then_branch = self.parse_block()?;
+
+        let else_branch = if let TokenType::Else = self.current_token.token {
+            self.advance(); // consume 'else'
+
+            if let TokenType::If = self.current_token.token {
+                let start = self.current_token.span;
+                let else_if = self.parse_if()?;
+                Some(vec![Statement::new(else_if, self.span_from(start))])
+            } else {
+                Some(self.parse_block()?)
+            }
+        } else {
+            None
+        };
+
+        Ok(StatementKind::If(condition, then_branch, else_branch))
+    }
+
+    /// Parse the "while" statement
+    /// E.g.
+    /// ```
+    ///  while x < 10 {
+    ///      x = x + 1;
+    ///  }
+    ///```
+    fn parse_while(&mut self) -> Result<StatementKind, ParseError> {
+        self.advance(); // consume 'while'
+
+        let condition = self.parse_expression()?;
+        let body = self.parse_loop_body()?;
+
+        Ok(StatementKind::While(condition, body))
+    }
+
+    /// Parse the "for" statement
+    /// E.g.
+    /// ```
+    ///  for i in 0..len {
+    ///      print i;
+    ///  }
+    ///```
+    fn parse_for(&mut self) -> Result<StatementKind, ParseError> {
+        self.advance(); // consume 'for'
+
+        let name = if let TokenType::Identifier(name) = &self.current_token.token {
+            let name = name.clone();
             self.advance();
+            name
+        } else {
+            return Err(self.unexpected("loop variable"));
+        };
+
+        self.expect(TokenType::In, "'in'")?;
+        let start = self.parse_expression()?;
+        self.expect(TokenType::DotDot, "'..'")?;
+        let end = self.parse_expression()?;
+        let body = self.parse_loop_body()?;
+
+        Ok(StatementKind::For(name, start, end, body))
+    }
+
+    fn parse_loop_body(&mut self) -> Result<Vec<Statement>, ParseError> {
+        self.loop_depth += 1;
+        let body = self.parse_block();
+        self.loop_depth -= 1;
+        body
+    }
+
+    /// Parse "break" and "continue"
+    fn parse_loop_jump(&mut self) -> Result<StatementKind, ParseError> {
+        let (stmt, keyword) = match self.current_token.token {
+            TokenType::Break => (StatementKind::Break, "break"),
+            _ => (StatementKind::Continue, "continue"),
+        };
+
+        if self.loop_depth == 0 {
+            self.report(self.error(ParseErrorKind::OutsideLoop(keyword)));
         }
-        let expr = self.parse_expression()?;
 
-        if let TokenType::RParen = self.current_token.token {
+        self.advance(); // consume 'break' or 'continue'
+        self.consume_semicolon()?;
+
+        Ok(stmt)
+    }
+
+    /// Parse the function declaration
+    /// E.g.
+    /// ```
+    ///  fn add(a, b) {
+    ///      return a + b;
+    ///  }
+    ///```
+    fn parse_function(&mut self) -> Result<StatementKind, ParseError> {
+        let start = self.current_token.span;
+        self.advance(); // consume 'fn'
+
+        let name = if let TokenType::Identifier(name) = &self.current_token.token {
+            let name = name.clone();
+            self.advance();
+            name
+        } else {
+            return Err(self.unexpected("function name"));
+        };
+
+        self.expect(TokenType::LParen, "'('")?;
+        let params = self.parse_params(TokenType::RParen, "')'")?;
+        let span = self.span_from(start);
+        let body = self.parse_function_body(|parser| parser.parse_block())?;
+
+        Ok(StatementKind::Function(Rc::new(FunctionDecl {
+            name,
+            params,
+            body,
+            span,
+        })))
+    }
+
+    /// Parse anonymous function, either `fn(params) { block }` or `|params| expression`
+    /// where the expression is the return value and may be a block instead.
+    /// E.g.
+    /// ```
+    ///  let double = |x| x * 2;
+    ///  let next = fn() { n = n + 1; return n; };
+    ///```
+    fn parse_lambda(&mut self) -> Result<Expression, ParseError> {
+        let start = self.current_token.span;
+
+        let params = match self.current_token.token {
+            TokenType::Function => {
+                self.advance(); // consume 'fn'
+                self.expect(TokenType::LParen, "'('")?;
+                self.parse_params(TokenType::RParen, "')'")?
+            }
+            // `||` is lexed as a single token, it's the empty parameter list here
+            TokenType::Or => {
+                self.advance();
+                Vec::new()
+            }
+            _ => {
+                self.advance(); // consume '|'
+                self.parse_params(TokenType::Pipe, "'|'")?
+            }
+        };
+        let signature = self.span_from(start);
+
+        let body = self.parse_function_body(|parser| {
+            if let TokenType::LBrace = parser.current_token.token {
+                parser.parse_block()
+            } else {
+                let value = parser.parse_expression()?;
+                let span = value.span;
+                Ok(vec![Statement::new(
+                    StatementKind::Return(Some(value)),
+                    span,
+                )])
+            }
+        })?;
+
+        let decl = FunctionDecl {
+            name: "anonymous".into(),
+            params,
+            body,
+            span: signature,
+        };
+        Ok(Expression::new(
+            ExpressionKind::Lambda(Rc::new(decl)),
+            self.span_from(start),
+        ))
+    }
+
+    /// Parse comma separated parameter names up to and including the `closing` token
+    fn parse_params(
+        &mut self,
+        closing: TokenType,
+        what: &str,
+    ) -> Result<Vec<EcoString>, ParseError> {
+        let mut params: Vec<(EcoString, Span)> = Vec::new();
+        if self.current_token.token != closing {
+            loop {
+                match &self.current_token.token {
+                    TokenType::Identifier(param) => {
+                        let param = param.clone();
+                        if let Some((_, first)) = params.iter().find(|(name, _)| *name == param) {
+                            let err = self.error(ParseErrorKind::DuplicateParameter {
+                                name: param.clone(),
+                                first: *first,
+                            });
+                            self.report(err);
+                        }
+                        params.push((param, self.current_token.span));
+                        self.advance();
+                    }
+                    _ => return Err(self.unexpected("parameter name")),
+                }
+
+                if self.current_token.token != TokenType::Comma {
+                    break;
+                }
+                self.advance(); // consume ','
+            }
+        }
+        self.expect(closing, what)?;
+
+        Ok(params.into_iter().map(|(name, _)| name).collect())
+    }
+
+    fn parse_function_body(
+        &mut self,
+        parse: impl FnOnce(&mut Self) -> Result<Vec<Statement>, ParseError>,
+    ) -> Result<Vec<Statement>, ParseError> {
+        // `break` can't jump out of the function into a loop around its declaration
+        let loop_depth = std::mem::take(&mut self.loop_depth);
+        let body = parse(self);
+        self.loop_depth = loop_depth;
+        body
+    }
+
+    /// Parse the "return" statement
+    /// E.g.
+    /// ```return a + b;```
+    fn parse_return(&mut self) -> Result<StatementKind, ParseError> {
+        self.advance(); // consume 'return'
+
+        let value = match self.current_token.token {
+            TokenType::Semicolon | TokenType::RBrace | TokenType::EOF => None,
+            _ => Some(self.parse_expression()?),
+        };
+        self.consume_semicolon()?;
+
+        Ok(StatementKind::Return(value))
+    }
+
+    /// Consume the expected token, `what` describes it in the error message
+    fn expect(&mut self, token: TokenType, what: &str) -> Result<(), ParseError> {
+        if self.current_token.token == token {
             self.advance();
+            Ok(())
+        } else {
+            Err(self.unexpected(what))
         }
+    }
 
-        Ok(Statement::Print(expr))
+    /// Parse statements enclosed in `{` and `}`
+    fn parse_block(&mut self) -> Result<Vec<Statement>, ParseError> {
+        let open = self.current_token.span;
+        if let TokenType::LBrace = self.current_token.token {
+            self.advance(); // consume '{'
+        } else {
+            return Err(self.unexpected("'{'"));
+        }
+
+        let mut statements = Vec::new();
+        loop {
+            match self.current_token.token {
+                TokenType::RBrace => {
+                    self.advance(); // consume '}'
+                    return Ok(statements);
+                }
+                TokenType::EOF => return Err(self.error(ParseErrorKind::UnclosedBlock { open })),
+                _ => self.parse_recovering(&mut statements),
+            }
+        }
+    }
+
+    /// Parse an expression used as a statement
+    /// E.g.
+    /// ```x = x + 1;```
+    fn parse_expression_statement(&mut self) -> Result<StatementKind, ParseError> {
+        let expr = self.parse_expression()?;
+        self.consume_semicolon()?;
+
+        Ok(StatementKind::Expression(expr))
     }
 
     /// Parse expression
-    fn parse_expression(&mut self) -> Result<Expression, String> {
-        let mut left = self.parse_primary()?; // Parse the left side of the expression
+    fn parse_expression(&mut self) -> Result<Expression, ParseError> {
+        self.parse_assignment()
+    }
+
+    /// Parse assignment, which binds loosest and groups to the right,
+    /// so `a = b = 1` assigns 1 to both.
+    fn parse_assignment(&mut self) -> Result<Expression, ParseError> {
+        let target = self.parse_binary(0)?;
+
+        if let TokenType::Assign = self.current_token.token {
+            self.advance(); // consume '='
+            let value = self.parse_assignment()?;
+            let span = target.span.to(value.span);
+
+            return match target.kind {
+                ExpressionKind::Identifier(name, _) => Ok(Expression::new(
+                    ExpressionKind::Assign(name, None, Box::new(value)),
+                    span,
+                )),
+                _ => {
+                    self.report(ParseError::new(
+                        ParseErrorKind::InvalidAssignmentTarget,
+                        target.span,
+                    ));
+                    Ok(target)
+                }
+            };
+        }
+
+        Ok(target)
+    }
 
-        while let Some(op) = self.parse_operator() {
-            let right = self.parse_primary()?; // Parse the right side of the expression
-            left = Expression::Binary(Box::new(left), op, Box::new(right));
+    /// Parse binary expression using precedence climbing.
+    /// Operators binding tighter than `min_precedence` are folded into the right operand,
+    /// so `2 + 3 * 4` becomes `2 + (3 * 4)` and `8 - 2 - 1` stays left associative.
+    fn parse_binary(&mut self, min_precedence: u8) -> Result<Expression, ParseError> {
+        let mut left = self.parse_unary()?;
+
+        while let Some(precedence) = Self::binary_precedence(&self.current_token.token) {
+            if precedence < min_precedence {
+                break;
+            }
+
+            let op = self.current_token.token.clone();
+            self.advance();
+
+            let right = self.parse_binary(precedence + 1)?;
+            let span = left.span.to(right.span);
+            let kind = match op {
+                TokenType::And | TokenType::Or => {
+                    ExpressionKind::Logical(Box::new(left), op, Box::new(right))
+                }
+                _ => ExpressionKind::Binary(Box::new(left), op, Box::new(right)),
+            };
+            left = Expression::new(kind, span);
         }
 
         Ok(left)
     }
 
-    fn parse_primary(&mut self) -> Result<Expression, String> {
-        match &self.current_token.token {
-            TokenType::Int(value) => {
-                let value = *value;
-                self.advance();
-                Ok(Expression::Int(value))
+    /// Parse prefix operator
+    /// E.g.
+    /// ```let x = -y * 2 == !done;```
+    fn parse_unary(&mut self) -> Result<Expression, ParseError> {
+        let start = self.current_token.span;
+
+        let kind = match self.current_token.token {
+            TokenType::Minus => {
+                self.advance(); // consume '-'
+                let operand = self.parse_unary()?;
+                ExpressionKind::Negation(Box::new(operand))
+            }
+            TokenType::Bang => {
+                self.advance(); // consume '!'
+                let operand = self.parse_unary()?;
+                ExpressionKind::Not(Box::new(operand))
             }
+            _ => return self.parse_call(),
+        };
 
-            TokenType::Float(value) => {
-                let value = *value;
-                self.advance();
-                Ok(Expression::Float(value))
+        Ok(Expression::new(kind, self.span_from(start)))
+    }
+
+    /// Parse call, index and slice expressions, they chain so `make()(1)` calls
+    /// the result of `make()` and `name[1..][0]` indexes the slice.
+    /// E.g.
+    /// ```let sum = add(1, 2 * x) + len(name[1..3]);```
+    fn parse_call(&mut self) -> Result<Expression, ParseError> {
+        let mut expr = self.parse_primary()?;
+
+        loop {
+            match self.current_token.token {
+                TokenType::LParen => expr = self.parse_arguments(expr)?,
+                TokenType::LBracket => expr = self.parse_index(expr)?,
+                _ => return Ok(expr),
             }
+        }
+    }
 
-            TokenType::Identifier(name) => {
-                let name = name.clone();
-                self.advance();
-                Ok(Expression::Identifier(name))
+    fn parse_index(&mut self, value: Expression) -> Result<Expression, ParseError> {
+        let value_span = value.span;
+        self.advance(); // consume '['
+
+        let start = match self.current_token.token {
+            TokenType::DotDot => None,
+            _ => Some(Box::new(self.parse_expression()?)),
+        };
+
+        let kind = if let TokenType::DotDot = self.current_token.token {
+            self.advance(); // consume '..'
+            let end = match self.current_token.token {
+                TokenType::RBracket => None,
+                _ => Some(Box::new(self.parse_expression()?)),
+            };
+            ExpressionKind::Slice(Box::new(value), start, end)
+        } else {
+            // `start` is only missing when a '..' follows
+            ExpressionKind::Index(Box::new(value), start.unwrap())
+        };
+        self.expect(TokenType::RBracket, "']'")?;
+
+        Ok(Expression::new(kind, self.span_from(value_span)))
+    }
+
+    fn parse_arguments(&mut self, callee: Expression) -> Result<Expression, ParseError> {
+        self.advance(); // consume '('
+
+        let mut args = Vec::new();
+        if self.current_token.token != TokenType::RParen {
+            loop {
+                args.push(self.parse_expression()?);
+
+                if self.current_token.token != TokenType::Comma {
+                    break;
+                }
+                self.advance(); // consume ','
+            }
+        }
+        self.expect(TokenType::RParen, "')'")?;
+
+        let span = self.span_from(callee.span);
+        Ok(Expression::new(
+            ExpressionKind::Call(Box::new(callee), args),
+            span,
+        ))
+    }
+
+    fn parse_primary(&mut self) -> Result<Expression, ParseError> {
+        let span = self.current_token.span;
+
+        let kind = match &self.current_token.token {
+            TokenType::Int(value) => ExpressionKind::Int(*value),
+            TokenType::Float(value) => ExpressionKind::Float(*value),
+            TokenType::Str(value) => ExpressionKind::Str(value.clone()),
+            TokenType::InterpolatedStr(parts) => Self::parse_interpolation(parts, span)?,
+            TokenType::True => ExpressionKind::Bool(true),
+            TokenType::False => ExpressionKind::Bool(false),
+            TokenType::Nil => ExpressionKind::Nil,
+            TokenType::Identifier(name) => ExpressionKind::Identifier(name.clone(), None),
+
+            TokenType::Function | TokenType::Pipe | TokenType::Or => return self.parse_lambda(),
+
+            TokenType::LParen => {
+                self.advance(); // consume '('
+                let expr = self.parse_expression()?;
+
+                if let TokenType::RParen = self.current_token.token {
+                    self.advance(); // consume ')'
+                    return Ok(Expression::new(expr.kind, self.span_from(span)));
+                } else {
+                    return Err(self.error(ParseErrorKind::UnclosedParen {
+                        found: self.current_token.token.clone(),
+                        open: span,
+                    }));
+                }
             }
 
             // Explicitly handle EOF here for clarity
-            TokenType::EOF => Err("Unexpected end of input while parsing expression.".into()),
+            TokenType::EOF => {
+                return Err(self.error(ParseErrorKind::UnexpectedEof {
+                    context: "expression",
+                }))
+            }
 
-            _ => Err(format!(
-                "Unexpected token in expression: {:?}",
-                self.current_token
-            )),
-        }
+            _ => {
+                return Err(self.error(ParseErrorKind::ExpectedExpression(
+                    self.current_token.token.clone(),
+                )))
+            }
+        };
+
+        // every literal and identifier is a single token
+        self.advance();
+        Ok(Expression::new(kind, span))
     }
 
-    /// Parse the statement that contains Binary or Arithmetic operator
-    /// E.g.
-    /// ```let x = 5 + y - 20;```
-    fn parse_operator(&mut self) -> Option<TokenType> {
-        match &self.current_token.token {
-            TokenType::Plus | TokenType::Minus | TokenType::Star | TokenType::Slash => {
-                let op = self.current_token.token.clone();
-                self.advance();
-                Some(op)
+    /// Parse the embedded expressions of an interpolated string, each with its own lexer.
+    /// Literal segments get the span of the whole string.
+    fn parse_interpolation(parts: &[StrPart], span: Span) -> Result<ExpressionKind, ParseError> {
+        let mut segments = Vec::with_capacity(parts.len());
+
+        for part in parts {
+            match part {
+                StrPart::Literal(text) => {
+                    segments.push(Expression::new(ExpressionKind::Str(text.clone()), span))
+                }
+                StrPart::Code(code, offset) => {
+                    let mut lexer = Lexer::with_offset(code, *offset);
+                    let mut parser = Parser::new(&mut lexer);
+
+                    let expr = match parser.current_token.token {
+                        TokenType::EOF => Err(parser.error(ParseErrorKind::EmptyInterpolation)),
+                        _ => parser.parse_expression(),
+                    };
+
+                    // the embedded code has no statements to recover at, stop at its first error
+                    if !parser.errors.is_empty() {
+                        return Err(parser.errors.remove(0));
+                    }
+                    segments.push(expr?);
+
+                    if parser.current_token.token != TokenType::EOF {
+                        return Err(parser.error(ParseErrorKind::TrailingInterpolation(
+                            parser.current_token.token.clone(),
+                        )));
+                    }
+                }
             }
+        }
+
+        Ok(ExpressionKind::Interpolation(segments))
+    }
+
+    /// Binding power of binary operators, higher binds tighter.
+    /// Returns `None` for tokens that don't continue an expression.
+    fn binary_precedence(token: &TokenType) -> Option<u8> {
+        match token {
+            TokenType::Or => Some(1),
+            TokenType::And => Some(2),
+            TokenType::Equal | TokenType::NotEqual => Some(3),
+            TokenType::Lt | TokenType::Gt | TokenType::LtEq | TokenType::GtEq => Some(4),
+            TokenType::Plus | TokenType::Minus => Some(5),
+            TokenType::Star | TokenType::Slash => Some(6),
             _ => None,
         }
     }
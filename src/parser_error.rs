@@ -0,0 +1,103 @@
+use crate::diagnostic::Diagnostic;
+use crate::lexer_error::LexerError;
+use crate::span::Span;
+use crate::token::TokenType;
+use ecow::EcoString;
+use thiserror::Error;
+
+/// A syntax error, `span` is where it is reported
+#[derive(Debug, Clone, Error)]
+#[error("{kind}")]
+pub struct ParseError {
+    pub kind: ParseErrorKind,
+    pub span: Span,
+}
+
+#[derive(Debug, Clone, Error)]
+pub enum ParseErrorKind {
+    #[error(transparent)]
+    Lexer(#[from] LexerError),
+    /// `expected` describes what was expected, e.g. `"'='"` or `"identifier"`
+    #[error("Expected {expected}, found {found}")]
+    UnexpectedToken {
+        expected: EcoString,
+        found: TokenType,
+    },
+    #[error("Expected expression, found {0}")]
+    ExpectedExpression(TokenType),
+    /// Input ended in the middle of a construct, `context` names it
+    #[error("Unexpected end of input while parsing {context}")]
+    UnexpectedEof { context: &'static str },
+    /// The span is where the `;` should go, `found` is what came instead
+    #[error("Expected ';', found {found}")]
+    MissingSemicolon { found: TokenType, found_span: Span },
+    #[error("Expected ')', found {found}")]
+    UnclosedParen { found: TokenType, open: Span },
+    #[error("Unexpected end of input while parsing block")]
+    UnclosedBlock { open: Span },
+    #[error("Invalid assignment target")]
+    InvalidAssignmentTarget,
+    #[error("'{0}' outside of a loop")]
+    OutsideLoop(&'static str),
+    #[error("Duplicate parameter '{name}'")]
+    DuplicateParameter { name: EcoString, first: Span },
+    #[error("Empty expression in string interpolation")]
+    EmptyInterpolation,
+    #[error("Unexpected token in string interpolation: {0}")]
+    TrailingInterpolation(TokenType),
+}
+
+impl ParseError {
+    pub fn new(kind: ParseErrorKind, span: Span) -> Self {
+        ParseError { kind, span }
+    }
+
+    /// The input ended in the middle of a construct, so more input could still make it valid
+    pub fn is_incomplete(&self) -> bool {
+        match &self.kind {
+            ParseErrorKind::Lexer(err) => matches!(
+                err,
+                LexerError::UnterminatedString
+                    | LexerError::UnterminatedInterpolation
+                    | LexerError::UnterminatedComment
+            ),
+            ParseErrorKind::UnexpectedEof { .. } | ParseErrorKind::UnclosedBlock { .. } => true,
+            ParseErrorKind::UnexpectedToken { found, .. }
+            | ParseErrorKind::ExpectedExpression(found)
+            | ParseErrorKind::UnclosedParen { found, .. } => *found == TokenType::EOF,
+            _ => false,
+        }
+    }
+}
+
+impl From<ParseError> for Diagnostic {
+    fn from(err: ParseError) -> Self {
+        let diagnostic = Diagnostic::error(err.to_string(), err.span);
+
+        match err.kind {
+            ParseErrorKind::MissingSemicolon { found_span, .. } => diagnostic
+                .with_label("expected ';' here")
+                .with_secondary(found_span, "unexpected token"),
+            ParseErrorKind::Lexer(LexerError::NumberTooLarge(literal))
+                if !literal.contains('.') =>
+            {
+                diagnostic.with_help(format!("integer literals can be at most {}", i64::MAX))
+            }
+            ParseErrorKind::UnclosedParen { open, .. } => {
+                diagnostic.with_secondary(open, "unclosed '(' opened here")
+            }
+            ParseErrorKind::UnclosedBlock { open } => diagnostic
+                .with_secondary(open, "block opened here")
+                .with_help("add a matching '}'"),
+            ParseErrorKind::InvalidAssignmentTarget => diagnostic
+                .with_label("cannot assign to this expression")
+                .with_help("only variables can be assigned to"),
+            ParseErrorKind::OutsideLoop(_) => diagnostic
+                .with_help("'break' and 'continue' can only be used inside 'while' and 'for'"),
+            ParseErrorKind::DuplicateParameter { first, .. } => {
+                diagnostic.with_secondary(first, "first declared here")
+            }
+            _ => diagnostic,
+        }
+    }
+}
@@ -0,0 +1,149 @@
+use crate::diagnostic::Diagnostic;
+use crate::environment::Environment;
+use crate::gc::GcConfig;
+use crate::lexer::Lexer;
+use crate::output;
+use crate::parser::{Parser, Statement, StatementKind};
+use crate::parser_error::ParseError;
+use crate::resolver::Resolver;
+use crate::span::SourceMap;
+use crate::value::Value;
+use rustyline::error::ReadlineError;
+use rustyline::DefaultEditor;
+use std::path::PathBuf;
+use std::process::ExitCode;
+
+const PROMPT: &str = ">> ";
+/// Prompt while the input so far is incomplete, like an open `{`
+const CONTINUATION_PROMPT: &str = ".. ";
+
+/// Read, evaluate and print until end of input (Ctrl-D).
+/// Variables and functions persist across inputs, the value of a bare expression is echoed.
+/// With `gc_stats`, the garbage collector statistics are printed when the session ends.
+pub fn run(gc: GcConfig, gc_stats: bool) -> ExitCode {
+    let mut editor = match DefaultEditor::new() {
+        Ok(editor) => editor,
+        Err(err) => {
+            eprintln!("error: Cannot start the REPL: {}", err);
+            return ExitCode::FAILURE;
+        }
+    };
+
+    let history = history_path();
+    if let Some(path) = &history {
+        // there is no history yet on the first run
+        let _ = editor.load_history(path);
+    }
+
+    let mut environment = Environment::with_gc(gc);
+    // Every input submitted so far. Each input is lexed at its offset in here,
+    // so spans from earlier inputs, like a function's definition, still point at real code.
+    let mut session = String::new();
+    let mut buffer = String::new();
+
+    loop {
+        let prompt = if buffer.is_empty() {
+            PROMPT
+        } else {
+            CONTINUATION_PROMPT
+        };
+
+        let line = match editor.readline(prompt) {
+            Ok(line) => line,
+            // Ctrl-C drops the pending input
+            Err(ReadlineError::Interrupted) => {
+                buffer.clear();
+                continue;
+            }
+            Err(ReadlineError::Eof) => break,
+            Err(err) => {
+                eprintln!("error: {}", err);
+                break;
+            }
+        };
+
+        // an empty line ends incomplete input, reporting whatever is missing
+        let force = line.trim().is_empty();
+        buffer.push_str(&line);
+        buffer.push('\n');
+        if buffer.trim().is_empty() {
+            buffer.clear();
+            continue;
+        }
+
+        let result = parse(&buffer, session.len());
+        if let Err(errors) = &result {
+            if !force && errors.last().is_some_and(ParseError::is_incomplete) {
+                continue;
+            }
+        }
+
+        let _ = editor.add_history_entry(buffer.trim_end());
+        session.push_str(&buffer);
+        buffer.clear();
+
+        let source_map = SourceMap::new("<repl>", &session);
+        match result {
+            Ok(mut statements) => match Resolver::new().resolve(&mut statements) {
+                Ok(()) => eval(&mut environment, &statements, &source_map),
+                Err(errors) => {
+                    for e in errors {
+                        Diagnostic::from(e).emit(&source_map);
+                    }
+                }
+            },
+            Err(errors) => {
+                for e in errors {
+                    Diagnostic::from(e).emit(&source_map);
+                }
+            }
+        }
+    }
+
+    if let Some(path) = &history {
+        if let Err(err) = editor.save_history(path) {
+            eprintln!(
+                "error: Cannot save history to '{}': {}",
+                path.display(),
+                err
+            );
+        }
+    }
+
+    if gc_stats {
+        eprintln!("gc: {}", environment.gc_stats());
+    }
+    ExitCode::SUCCESS
+}
+
+/// Parse one input starting at byte `offset` of the session
+fn parse(input: &str, offset: usize) -> Result<Vec<Statement>, Vec<ParseError>> {
+    let mut lexer = Lexer::with_offset(input, offset);
+    let mut parser = Parser::new(&mut lexer);
+    parser.parse_program()
+}
+
+/// Run the statements of one input, echoing the value of expression statements.
+/// A runtime error is reported and skips the rest of the input.
+fn eval(environment: &mut Environment, statements: &[Statement], source_map: &SourceMap) {
+    for statement in statements {
+        let result = match &statement.kind {
+            StatementKind::Expression(expr) => environment.eval_expression(expr).map(|value| {
+                if value != Value::Nil {
+                    output::println(value);
+                }
+            }),
+            _ => environment.execute(statement).map(|_| ()),
+        };
+
+        if let Err(e) = result {
+            Diagnostic::from(e).emit(source_map);
+            return;
+        }
+    }
+}
+
+/// History is kept in `~/.relox_history`
+fn history_path() -> Option<PathBuf> {
+    std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".relox_history"))
+}
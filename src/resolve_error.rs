@@ -0,0 +1,46 @@
+use crate::diagnostic::Diagnostic;
+use crate::span::Span;
+use ecow::EcoString;
+use thiserror::Error;
+
+/// An error in how a syntactically valid program uses its variables, found before it runs
+#[derive(Debug, Clone, Error)]
+#[error("{kind}")]
+pub struct ResolveError {
+    pub kind: ResolveErrorKind,
+    pub span: Span,
+}
+
+#[derive(Debug, Clone, Error)]
+pub enum ResolveErrorKind {
+    #[error("Cannot read local variable '{0}' in its own initializer")]
+    OwnInitializer(EcoString),
+    #[error("'return' outside of a function")]
+    ReturnOutsideFunction,
+    #[error("Variable '{name}' is already declared in this scope")]
+    DuplicateDeclaration { name: EcoString, first: Span },
+}
+
+impl ResolveError {
+    pub fn new(kind: ResolveErrorKind, span: Span) -> Self {
+        ResolveError { kind, span }
+    }
+}
+
+impl From<ResolveError> for Diagnostic {
+    fn from(err: ResolveError) -> Self {
+        let diagnostic = Diagnostic::error(err.to_string(), err.span);
+
+        match err.kind {
+            ResolveErrorKind::OwnInitializer(_) => diagnostic
+                .with_label("used before it is initialized")
+                .with_help("rename the new variable to refer to an outer one with the same name"),
+            ResolveErrorKind::ReturnOutsideFunction => {
+                diagnostic.with_help("at the top level, end the script early with an 'if' instead")
+            }
+            ResolveErrorKind::DuplicateDeclaration { first, .. } => diagnostic
+                .with_secondary(first, "first declared here")
+                .with_help("assign to it instead, or declare the new one in a nested block"),
+        }
+    }
+}
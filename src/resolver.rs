@@ -0,0 +1,227 @@
+use crate::parser::{Expression, ExpressionKind, FunctionDecl, Slot, Statement, StatementKind};
+use crate::resolve_error::{ResolveError, ResolveErrorKind};
+use crate::span::Span;
+use ecow::EcoString;
+use std::rc::Rc;
+
+/// Variable declared in a local scope
+struct Declared {
+    name: EcoString,
+    /// Declaration it comes from, where duplicate declaration errors point
+    span: Span,
+    /// False while its initializer is being resolved
+    defined: bool,
+}
+
+/// Static pass between the `Parser` and the `Environment` binding every use of a local
+/// variable to its `Slot`. Its scopes mirror the ones the `Environment` creates at runtime,
+/// so a slot is counted in scopes and declarations exactly like the evaluator stores them.
+/// Variables declared at the top level are globals, which are looked up by name and may be
+/// redeclared, so the REPL can define a variable again.
+pub struct Resolver {
+    /// Local scopes, innermost last, empty at the top level
+    scopes: Vec<Vec<Declared>>,
+    /// Number of functions enclosing the statement being resolved
+    function_depth: usize,
+    errors: Vec<ResolveError>,
+}
+
+impl Resolver {
+    pub fn new() -> Self {
+        Resolver {
+            scopes: Vec::new(),
+            function_depth: 0,
+            errors: Vec::new(),
+        }
+    }
+
+    /// Resolve a whole program in place, reporting every error in order of position
+    pub fn resolve(mut self, statements: &mut [Statement]) -> Result<(), Vec<ResolveError>> {
+        self.statements(statements);
+        if self.errors.is_empty() {
+            return Ok(());
+        }
+        self.errors.sort_by_key(|err| err.span.start);
+        Err(self.errors)
+    }
+
+    fn error(&mut self, kind: ResolveErrorKind, span: Span) {
+        self.errors.push(ResolveError::new(kind, span));
+    }
+
+    /// Declare a variable in the innermost local scope, not yet usable by its initializer
+    fn declare(&mut self, name: &EcoString, span: Span) {
+        let Some(scope) = self.scopes.last_mut() else {
+            return;
+        };
+
+        if let Some(first) = scope.iter().find(|declared| declared.name == *name) {
+            let kind = ResolveErrorKind::DuplicateDeclaration {
+                name: name.clone(),
+                first: first.span,
+            };
+            self.error(kind, span);
+        }
+
+        // declared even when it's a duplicate, so the slots of the following ones still match
+        self.scopes.last_mut().unwrap().push(Declared {
+            name: name.clone(),
+            span,
+            defined: false,
+        });
+    }
+
+    /// Mark the last declaration of the innermost scope as initialized
+    fn define(&mut self) {
+        if let Some(declared) = self.scopes.last_mut().and_then(|scope| scope.last_mut()) {
+            declared.defined = true;
+        }
+    }
+
+    /// Slot of the nearest local named `name`, `None` for a global
+    fn lookup(&self, name: &str) -> Option<Slot> {
+        self.scopes
+            .iter()
+            .rev()
+            .enumerate()
+            .find_map(|(depth, scope)| {
+                let index = scope.iter().rposition(|declared| declared.name == name)?;
+                Some(Slot { depth, index })
+            })
+    }
+
+    fn statements(&mut self, statements: &mut [Statement]) {
+        for stmt in statements {
+            self.statement(stmt);
+        }
+    }
+
+    /// Resolve statements run in a scope of their own, like a block or a loop body
+    fn block(&mut self, statements: &mut [Statement]) {
+        self.scopes.push(Vec::new());
+        self.statements(statements);
+        self.scopes.pop();
+    }
+
+    fn statement(&mut self, stmt: &mut Statement) {
+        match &mut stmt.kind {
+            StatementKind::Let(name, value) | StatementKind::Const(name, value) => {
+                self.declare(name, stmt.span);
+                self.expression(value);
+                self.define();
+            }
+            StatementKind::Print(expr) | StatementKind::Expression(expr) => self.expression(expr),
+            StatementKind::If(condition, then_branch, else_branch) => {
+                self.expression(condition);
+                self.block(then_branch);
+                if let Some(else_branch) = else_branch {
+                    self.block(else_branch);
+                }
+            }
+            StatementKind::Block(statements) => self.block(statements),
+            StatementKind::While(condition, body) => {
+                self.expression(condition);
+                self.block(body);
+            }
+            StatementKind::For(name, start, end, body) => {
+                self.expression(start);
+                self.expression(end);
+
+                // the loop variable shares the scope of the body, like parameters do
+                self.scopes.push(Vec::new());
+                self.declare(name, stmt.span);
+                self.define();
+                self.statements(body);
+                self.scopes.pop();
+            }
+            StatementKind::Break | StatementKind::Continue => {}
+            StatementKind::Function(decl) => {
+                // defined before its body is resolved so it can call itself
+                self.declare(&decl.name, decl.span);
+                self.define();
+                self.function(decl);
+            }
+            StatementKind::Return(value) => {
+                if self.function_depth == 0 {
+                    self.error(ResolveErrorKind::ReturnOutsideFunction, stmt.span);
+                }
+                if let Some(value) = value {
+                    self.expression(value);
+                }
+            }
+        }
+    }
+
+    /// Resolve the body of a function in a scope holding its parameters.
+    /// The declaration isn't shared with any value yet, so it's updated in place.
+    fn function(&mut self, decl: &mut Rc<FunctionDecl>) {
+        let decl = Rc::make_mut(decl);
+
+        self.scopes.push(Vec::new());
+        for param in &decl.params {
+            self.declare(param, decl.span);
+            self.define();
+        }
+
+        self.function_depth += 1;
+        self.statements(&mut decl.body);
+        self.function_depth -= 1;
+        self.scopes.pop();
+    }
+
+    fn expression(&mut self, expr: &mut Expression) {
+        match &mut expr.kind {
+            ExpressionKind::Int(_)
+            | ExpressionKind::Float(_)
+            | ExpressionKind::Str(_)
+            | ExpressionKind::Bool(_)
+            | ExpressionKind::Nil => {}
+            ExpressionKind::Interpolation(parts) => {
+                for part in parts {
+                    self.expression(part);
+                }
+            }
+            ExpressionKind::Identifier(name, slot) => {
+                let uninitialized = self.scopes.last().is_some_and(|scope| {
+                    scope
+                        .iter()
+                        .rev()
+                        .find(|declared| declared.name == *name)
+                        .is_some_and(|declared| !declared.defined)
+                });
+                if uninitialized {
+                    self.error(ResolveErrorKind::OwnInitializer(name.clone()), expr.span);
+                }
+                *slot = self.lookup(name);
+            }
+            ExpressionKind::Assign(name, slot, value) => {
+                self.expression(value);
+                *slot = self.lookup(name);
+            }
+            ExpressionKind::Negation(operand) | ExpressionKind::Not(operand) => {
+                self.expression(operand)
+            }
+            ExpressionKind::Binary(left, _, right) | ExpressionKind::Logical(left, _, right) => {
+                self.expression(left);
+                self.expression(right);
+            }
+            ExpressionKind::Call(callee, args) => {
+                self.expression(callee);
+                for arg in args {
+                    self.expression(arg);
+                }
+            }
+            ExpressionKind::Index(value, index) => {
+                self.expression(value);
+                self.expression(index);
+            }
+            ExpressionKind::Slice(value, start, end) => {
+                self.expression(value);
+                for bound in [start, end].into_iter().flatten() {
+                    self.expression(bound);
+                }
+            }
+            ExpressionKind::Lambda(decl) => self.function(decl),
+        }
+    }
+}
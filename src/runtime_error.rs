@@ -0,0 +1,84 @@
+use crate::diagnostic::Diagnostic;
+use crate::span::Span;
+use crate::token::TokenType;
+use ecow::EcoString;
+use thiserror::Error;
+
+/// An error raised while running a program, `span` is the expression or statement that failed
+#[derive(Debug, Clone, Error)]
+#[error("{kind}")]
+pub struct RuntimeError {
+    pub kind: RuntimeErrorKind,
+    pub span: Span,
+}
+
+#[derive(Debug, Clone, Error)]
+pub enum RuntimeErrorKind {
+    #[error("Variable '{0}' not found")]
+    UndefinedVariable(EcoString),
+    #[error("Cannot assign to undeclared variable '{0}'")]
+    UndeclaredAssignment(EcoString),
+    #[error("Cannot assign to constant '{0}'")]
+    AssignToConstant(EcoString),
+    #[error("Cannot redeclare constant '{0}'")]
+    RedeclareConstant(EcoString),
+    #[error("Division by zero")]
+    DivisionByZero,
+    #[error("Integer overflow")]
+    IntegerOverflow,
+    /// A value of the wrong type, `expected` describes what would have worked
+    #[error("Expected {expected}, found {found}")]
+    TypeMismatch {
+        expected: &'static str,
+        found: &'static str,
+    },
+    #[error("Unsupported operand types for {op}: {left} and {right}")]
+    InvalidOperands {
+        op: TokenType,
+        left: &'static str,
+        right: &'static str,
+    },
+    #[error("Index {index} out of range for length {len}")]
+    IndexOutOfRange { index: i64, len: usize },
+    #[error("Slice start {start} is greater than end {end}")]
+    InvalidSlice { start: usize, end: usize },
+    /// `definition` is the signature of user-defined functions
+    #[error("Function '{name}' expects {expected} argument(s), got {found}")]
+    ArityMismatch {
+        name: EcoString,
+        expected: usize,
+        found: usize,
+        definition: Option<Span>,
+    },
+    #[error("Stack overflow: more than {0} nested calls")]
+    StackOverflow(usize),
+}
+
+impl RuntimeError {
+    pub fn new(kind: RuntimeErrorKind, span: Span) -> Self {
+        RuntimeError { kind, span }
+    }
+}
+
+impl From<RuntimeError> for Diagnostic {
+    fn from(err: RuntimeError) -> Self {
+        let diagnostic = Diagnostic::error(err.to_string(), err.span);
+
+        match err.kind {
+            RuntimeErrorKind::UndefinedVariable(_) => {
+                diagnostic.with_label("not found in this scope")
+            }
+            RuntimeErrorKind::AssignToConstant(_) => {
+                diagnostic.with_help("declare it with 'let' to allow assignment")
+            }
+            RuntimeErrorKind::ArityMismatch {
+                definition: Some(definition),
+                ..
+            } => diagnostic.with_secondary(definition, "function defined here"),
+            RuntimeErrorKind::StackOverflow(_) => {
+                diagnostic.with_help("check for recursion without a base case")
+            }
+            _ => diagnostic,
+        }
+    }
+}
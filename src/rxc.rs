@@ -0,0 +1,590 @@
+use crate::chunk::{Chunk, Constant, FunctionProto, OpCode};
+use crate::rxc_error::RxcError;
+use crate::span::Span;
+use std::rc::Rc;
+
+/// First bytes of every `.rxc` file
+const MAGIC: &[u8; 4] = b"RXC\0";
+
+/// Version of the encoding and of the instruction set it stores.
+/// Files with another version are rejected, so bump it whenever either changes.
+pub const FORMAT_VERSION: u16 = 1;
+
+// Tags of the constants in the constant pool
+const TAG_INT: u8 = 0;
+const TAG_FLOAT: u8 = 1;
+const TAG_STR: u8 = 2;
+const TAG_FUNCTION: u8 = 3;
+
+/// A program loaded from a `.rxc` file.
+/// The source is kept so runtime errors are reported like for the script itself.
+pub struct Compiled {
+    /// Name of the source file it was compiled from, as given to `relox compile`
+    pub name: String,
+    /// Absolute path of the source file, where `source_hash` is checked against
+    /// whatever the file holds now. Empty when the path couldn't be resolved.
+    pub path: String,
+    pub source: String,
+    /// `hash_source` of the source at the time it was compiled
+    pub source_hash: u64,
+    pub script: Rc<FunctionProto>,
+}
+
+impl Compiled {
+    /// Whether the source file now holds something else than what was compiled.
+    /// False when it can't be read, there is nothing to compare then.
+    pub fn source_changed(&self) -> bool {
+        std::fs::read_to_string(&self.path)
+            .is_ok_and(|current| hash_source(&current) != self.source_hash)
+    }
+}
+
+/// Whether `bytes` look like the content of a `.rxc` file
+pub fn is_bytecode(bytes: &[u8]) -> bool {
+    bytes.starts_with(MAGIC)
+}
+
+/// Hash of the source, see `fnv1a`
+fn hash_source(source: &str) -> u64 {
+    fnv1a(source.as_bytes())
+}
+
+/// FNV-1a hash, stable across platforms and builds unlike `DefaultHasher`
+fn fnv1a(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf29ce484222325, |hash, byte| {
+        (hash ^ *byte as u64).wrapping_mul(0x100000001b3)
+    })
+}
+
+/// Serialize a compiled program. Every integer is big endian, like the bytecode operands.
+/// `checksum` is the `fnv1a` hash of the encoded `script`.
+/// E.g.
+/// ```text
+///  "RXC\0" version:u16 source_hash:u64 name:str path:str source:str checksum:u64 script:function
+///  str      = len:u64 utf8 bytes
+///  function = name:str arity:u64 upvalue_count:u64 span chunk
+///  chunk    = code:(len:u64 bytes) constants:(len:u64 constant*) spans:(len:u64 (offset:u64 span)*)
+///  span     = start:u64 end:u64
+///```
+pub fn encode(script: &FunctionProto, name: &str, path: &str, source: &str) -> Vec<u8> {
+    let mut function = Encoder { out: Vec::new() };
+    function.function(script);
+
+    let mut encoder = Encoder { out: Vec::new() };
+    encoder.out.extend_from_slice(MAGIC);
+    encoder.u16(FORMAT_VERSION);
+    encoder.u64(hash_source(source));
+    encoder.str(name);
+    encoder.str(path);
+    encoder.str(source);
+    encoder.u64(fnv1a(&function.out));
+    encoder.out.extend_from_slice(&function.out);
+    encoder.out
+}
+
+/// Load a program serialized by `encode`.
+/// Besides the structure of the file, the checksum catches a damaged file and every
+/// instruction is checked to be one the `Vm` can decode, with its constants, upvalues,
+/// stack slots and jump targets in range, so a damaged file is rejected instead of crashing.
+/// Whether a captured local is closed before its slot is popped isn't checked, only the
+/// checksum covers it.
+pub fn decode(bytes: &[u8]) -> Result<Compiled, RxcError> {
+    let mut decoder = Decoder { bytes };
+    if decoder.take(MAGIC.len()).ok() != Some(MAGIC.as_slice()) {
+        return Err(RxcError::NotBytecode);
+    }
+
+    let version = decoder.u16()?;
+    if version != FORMAT_VERSION {
+        return Err(RxcError::IncompatibleVersion {
+            found: version,
+            expected: FORMAT_VERSION,
+        });
+    }
+
+    let source_hash = decoder.u64()?;
+    let name = decoder.str()?;
+    let path = decoder.str()?;
+    let source = decoder.str()?;
+    let checksum = decoder.u64()?;
+    let encoded = decoder.bytes;
+    let script = decoder.function()?;
+
+    if !decoder.bytes.is_empty() {
+        return Err(RxcError::Corrupt("unexpected data after the program"));
+    }
+    if fnv1a(encoded) != checksum {
+        return Err(RxcError::Corrupt("bytecode does not match its checksum"));
+    }
+    if hash_source(&source) != source_hash {
+        return Err(RxcError::Corrupt("source does not match its hash"));
+    }
+    if script.arity != 0 || script.upvalue_count != 0 {
+        return Err(RxcError::Corrupt(
+            "script takes parameters or captures variables",
+        ));
+    }
+    validate(&script, source.len())?;
+
+    Ok(Compiled {
+        name,
+        path,
+        source,
+        source_hash,
+        script: Rc::new(script),
+    })
+}
+
+/// Check that the `Vm` can run every instruction of `function` and of the functions nested
+/// in it without reading outside of the code, the constants, the upvalues or the stack
+fn validate(function: &FunctionProto, source_len: usize) -> Result<(), RxcError> {
+    let chunk = &function.chunk;
+    let code = &chunk.code;
+    let spans = chunk.spans.iter().map(|(_, span)| span);
+    if std::iter::once(&function.span)
+        .chain(spans)
+        .any(|span| span.start > span.end || span.end > source_len)
+    {
+        return Err(RxcError::Corrupt("span outside of the source"));
+    }
+
+    let constant = |offset: usize| -> Result<&Constant, RxcError> {
+        let index = chunk.read_u16(offset + 1) as usize;
+        chunk
+            .constants
+            .get(index)
+            .ok_or(RxcError::Corrupt("constant index out of range"))
+    };
+
+    // offset of the next instruction, for every offset an instruction starts at
+    let mut next = vec![None; code.len()];
+    let mut offset = 0;
+    while offset < code.len() {
+        let op = OpCode::try_from(code[offset]).map_err(|_| RxcError::Corrupt("invalid opcode"))?;
+        let operands = match op {
+            OpCode::Constant
+            | OpCode::GetGlobal
+            | OpCode::SetGlobal
+            | OpCode::DefineGlobal
+            | OpCode::DefineConstGlobal
+            | OpCode::AssignConstant
+            | OpCode::BuildString
+            | OpCode::Jump
+            | OpCode::JumpIfFalse
+            | OpCode::Loop => 2,
+            // each captured variable is described by two more operand bytes
+            OpCode::Closure => match constant(offset)? {
+                Constant::Function(nested) => 2 + nested.upvalue_count * 2,
+                _ => {
+                    return Err(RxcError::Corrupt(
+                        "closure of a constant that isn't a function",
+                    ))
+                }
+            },
+            OpCode::GetLocal
+            | OpCode::SetLocal
+            | OpCode::GetUpvalue
+            | OpCode::SetUpvalue
+            | OpCode::Slice
+            | OpCode::Call => 1,
+            _ => 0,
+        };
+        if offset + operands >= code.len() {
+            return Err(RxcError::Corrupt("instruction cut off"));
+        }
+
+        match op {
+            OpCode::Constant => {
+                if let Constant::Function(_) = constant(offset)? {
+                    return Err(RxcError::Corrupt("function used as a value"));
+                }
+            }
+            OpCode::GetGlobal
+            | OpCode::SetGlobal
+            | OpCode::DefineGlobal
+            | OpCode::DefineConstGlobal
+            | OpCode::AssignConstant => {
+                let Constant::Str(_) = constant(offset)? else {
+                    return Err(RxcError::Corrupt("variable name is not a string"));
+                };
+            }
+            OpCode::GetUpvalue | OpCode::SetUpvalue
+                if code[offset + 1] as usize >= function.upvalue_count =>
+            {
+                return Err(RxcError::Corrupt("upvalue index out of range"));
+            }
+            OpCode::Closure => {
+                for pair in code[offset + 3..offset + 1 + operands].chunks(2) {
+                    let (is_local, index) = (pair[0], pair[1] as usize);
+                    if is_local > 1 || (is_local == 0 && index >= function.upvalue_count) {
+                        return Err(RxcError::Corrupt("upvalue index out of range"));
+                    }
+                }
+            }
+            _ => {}
+        }
+
+        next[offset] = Some(offset + 1 + operands);
+        offset += 1 + operands;
+    }
+
+    // Follow every path through the code with the height of the stack above the frame's base,
+    // which holds the callee and its arguments when the function starts.
+    // Every path has to reach an instruction with the same height, like the compiler emits it.
+    let mut heights = vec![None; code.len()];
+    let mut pending = vec![(0, function.arity + 1)];
+    while let Some((offset, height)) = pending.pop() {
+        let Some(next) = next.get(offset).copied().flatten() else {
+            return Err(RxcError::Corrupt("jump out of the code"));
+        };
+        match heights[offset] {
+            Some(known) if known == height => continue,
+            Some(_) => return Err(RxcError::Corrupt("inconsistent stack height")),
+            None => heights[offset] = Some(height),
+        }
+
+        let op = OpCode::try_from(code[offset]).unwrap();
+        let operand = || code[offset + 1] as usize;
+        let (pops, pushes) = match op {
+            OpCode::Constant
+            | OpCode::Nil
+            | OpCode::True
+            | OpCode::False
+            | OpCode::GetUpvalue
+            | OpCode::GetGlobal => (0, 1),
+            OpCode::Closure => {
+                let mut captures = code[offset + 3..next].chunks(2);
+                if captures.any(|pair| pair[0] == 1 && pair[1] as usize > height) {
+                    return Err(RxcError::Corrupt("local slot out of range"));
+                }
+                (0, 1)
+            }
+            OpCode::GetLocal | OpCode::SetLocal if operand() >= height => {
+                return Err(RxcError::Corrupt("local slot out of range"));
+            }
+            OpCode::GetLocal => (0, 1),
+            OpCode::SetLocal
+            | OpCode::SetUpvalue
+            | OpCode::SetGlobal
+            | OpCode::AssignConstant
+            | OpCode::Negate
+            | OpCode::Not
+            | OpCode::CheckInt
+            | OpCode::JumpIfFalse => (1, 1),
+            OpCode::Pop
+            | OpCode::DefineGlobal
+            | OpCode::DefineConstGlobal
+            | OpCode::Print
+            | OpCode::CloseUpvalue
+            | OpCode::Return => (1, 0),
+            OpCode::Equal
+            | OpCode::NotEqual
+            | OpCode::Less
+            | OpCode::Greater
+            | OpCode::LessEqual
+            | OpCode::GreaterEqual
+            | OpCode::Add
+            | OpCode::Subtract
+            | OpCode::Multiply
+            | OpCode::Divide
+            | OpCode::Index => (2, 1),
+            OpCode::Slice => (1 + (operand() & 3).count_ones() as usize, 1),
+            OpCode::BuildString => (chunk.read_u16(offset + 1) as usize, 1),
+            OpCode::Call => (operand() + 1, 1),
+            OpCode::Jump | OpCode::Loop => (0, 0),
+        };
+        if height < pops {
+            return Err(RxcError::Corrupt("stack underflow"));
+        }
+        let height = height - pops + pushes;
+
+        let distance = || chunk.read_u16(offset + 1) as usize;
+        match op {
+            OpCode::Return => {}
+            OpCode::Jump => pending.push((next + distance(), height)),
+            OpCode::JumpIfFalse => {
+                pending.push((next + distance(), height));
+                pending.push((next, height));
+            }
+            OpCode::Loop => match next.checked_sub(distance()) {
+                Some(target) => pending.push((target, height)),
+                None => return Err(RxcError::Corrupt("jump out of the code")),
+            },
+            _ => pending.push((next, height)),
+        }
+    }
+
+    for constant in &chunk.constants {
+        if let Constant::Function(nested) = constant {
+            validate(nested, source_len)?;
+        }
+    }
+    Ok(())
+}
+
+struct Encoder {
+    out: Vec<u8>,
+}
+
+impl Encoder {
+    fn u16(&mut self, value: u16) {
+        self.out.extend_from_slice(&value.to_be_bytes());
+    }
+
+    fn u64(&mut self, value: u64) {
+        self.out.extend_from_slice(&value.to_be_bytes());
+    }
+
+    fn usize(&mut self, value: usize) {
+        self.u64(value as u64);
+    }
+
+    fn bytes(&mut self, bytes: &[u8]) {
+        self.usize(bytes.len());
+        self.out.extend_from_slice(bytes);
+    }
+
+    fn str(&mut self, string: &str) {
+        self.bytes(string.as_bytes());
+    }
+
+    fn span(&mut self, span: Span) {
+        self.usize(span.start);
+        self.usize(span.end);
+    }
+
+    fn function(&mut self, function: &FunctionProto) {
+        self.str(&function.name);
+        self.usize(function.arity);
+        self.usize(function.upvalue_count);
+        self.span(function.span);
+        self.chunk(&function.chunk);
+    }
+
+    fn chunk(&mut self, chunk: &Chunk) {
+        self.bytes(&chunk.code);
+
+        self.usize(chunk.constants.len());
+        for constant in &chunk.constants {
+            match constant {
+                Constant::Int(value) => {
+                    self.out.push(TAG_INT);
+                    self.out.extend_from_slice(&value.to_be_bytes());
+                }
+                Constant::Float(value) => {
+                    self.out.push(TAG_FLOAT);
+                    self.out.extend_from_slice(&value.to_be_bytes());
+                }
+                Constant::Str(value) => {
+                    self.out.push(TAG_STR);
+                    self.str(value);
+                }
+                Constant::Function(function) => {
+                    self.out.push(TAG_FUNCTION);
+                    self.function(function);
+                }
+            }
+        }
+
+        self.usize(chunk.spans.len());
+        for (offset, span) in &chunk.spans {
+            self.usize(*offset);
+            self.span(*span);
+        }
+    }
+}
+
+struct Decoder<'a> {
+    /// Input not decoded yet
+    bytes: &'a [u8],
+}
+
+impl<'a> Decoder<'a> {
+    fn take(&mut self, len: usize) -> Result<&'a [u8], RxcError> {
+        if self.bytes.len() < len {
+            return Err(RxcError::Truncated);
+        }
+        let (taken, rest) = self.bytes.split_at(len);
+        self.bytes = rest;
+        Ok(taken)
+    }
+
+    fn array<const N: usize>(&mut self) -> Result<[u8; N], RxcError> {
+        Ok(self.take(N)?.try_into().unwrap())
+    }
+
+    fn u8(&mut self) -> Result<u8, RxcError> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u16(&mut self) -> Result<u16, RxcError> {
+        Ok(u16::from_be_bytes(self.array()?))
+    }
+
+    fn u64(&mut self) -> Result<u64, RxcError> {
+        Ok(u64::from_be_bytes(self.array()?))
+    }
+
+    fn usize(&mut self) -> Result<usize, RxcError> {
+        usize::try_from(self.u64()?).map_err(|_| RxcError::Corrupt("length out of range"))
+    }
+
+    fn bytes(&mut self) -> Result<&'a [u8], RxcError> {
+        let len = self.usize()?;
+        self.take(len)
+    }
+
+    fn str(&mut self) -> Result<String, RxcError> {
+        let bytes = self.bytes()?;
+        String::from_utf8(bytes.to_vec()).map_err(|_| RxcError::Corrupt("string is not UTF-8"))
+    }
+
+    fn span(&mut self) -> Result<Span, RxcError> {
+        Ok(Span::new(self.usize()?, self.usize()?))
+    }
+
+    fn function(&mut self) -> Result<FunctionProto, RxcError> {
+        let name = self.str()?.into();
+        let arity = self.usize()?;
+        let upvalue_count = self.usize()?;
+        // both are operands of a single byte
+        if arity > u8::MAX as usize || upvalue_count > u8::MAX as usize + 1 {
+            return Err(RxcError::Corrupt("too many parameters or upvalues"));
+        }
+
+        Ok(FunctionProto {
+            name,
+            arity,
+            upvalue_count,
+            span: self.span()?,
+            chunk: self.chunk()?,
+        })
+    }
+
+    fn chunk(&mut self) -> Result<Chunk, RxcError> {
+        let code = self.bytes()?.to_vec();
+
+        // counts come from the file, so vectors grow as entries are actually read
+        let mut constants = Vec::new();
+        for _ in 0..self.usize()? {
+            let constant = match self.u8()? {
+                TAG_INT => Constant::Int(i64::from_be_bytes(self.array()?)),
+                TAG_FLOAT => Constant::Float(f64::from_be_bytes(self.array()?)),
+                TAG_STR => Constant::Str(self.str()?.into()),
+                TAG_FUNCTION => Constant::Function(Rc::new(self.function()?)),
+                _ => return Err(RxcError::Corrupt("unknown constant type")),
+            };
+            constants.push(constant);
+        }
+
+        let mut spans = Vec::new();
+        for _ in 0..self.usize()? {
+            spans.push((self.usize()?, self.span()?));
+        }
+        if spans.is_empty() && !code.is_empty() {
+            return Err(RxcError::Corrupt("missing line table"));
+        }
+
+        Ok(Chunk {
+            code,
+            constants,
+            spans,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::compiler::Compiler;
+    use crate::gc::GcConfig;
+    use crate::lexer::Lexer;
+    use crate::parser::Parser;
+    use crate::resolver::Resolver;
+    use crate::vm::Vm;
+
+    /// Closures, a local recursive function and constants, dividing by zero if they misbehave
+    const SOURCE: &str = "
+        fn make_counter() { let n = 0; return fn() { n = n + 1; return n; }; }
+        const counter = make_counter();
+        counter();
+        {
+            fn fact(n) { if n < 2 { return 1; } return n * fact(n - 1); }
+            if fact(5) + counter() != 122 { let failed = 1 / 0; }
+        }";
+
+    fn compile(source: &str) -> Rc<FunctionProto> {
+        let mut lexer = Lexer::new(source);
+        let mut statements = Parser::new(&mut lexer).parse_program().unwrap();
+        Resolver::new().resolve(&mut statements).unwrap();
+        Compiler::new().compile(&statements).unwrap()
+    }
+
+    fn encoded() -> Vec<u8> {
+        encode(&compile(SOURCE), "test.rx", "", SOURCE)
+    }
+
+    #[test]
+    fn decoded_program_runs() {
+        let script = compile(SOURCE);
+        let compiled = decode(&encode(&script, "test.rx", "", SOURCE)).unwrap();
+
+        assert_eq!(compiled.name, "test.rx");
+        assert_eq!(compiled.source, SOURCE);
+        assert_eq!(compiled.script, script);
+        assert!(Vm::new(GcConfig::default()).run(compiled.script).is_ok());
+    }
+
+    #[test]
+    fn flipped_byte_fails_the_checksum() {
+        let mut bytes = encoded();
+        // end of the last span, changing it keeps the file well formed
+        *bytes.last_mut().unwrap() ^= 1;
+
+        let err = decode(&bytes).err().unwrap();
+        assert!(matches!(
+            err,
+            RxcError::Corrupt("bytecode does not match its checksum")
+        ));
+    }
+
+    #[test]
+    fn truncated_file_is_rejected() {
+        let bytes = encoded();
+        let err = decode(&bytes[..bytes.len() - 1]).err().unwrap();
+        assert!(matches!(err, RxcError::Truncated));
+    }
+
+    #[test]
+    fn other_version_is_rejected() {
+        let mut bytes = encoded();
+        bytes[MAGIC.len()..MAGIC.len() + 2].copy_from_slice(&(FORMAT_VERSION + 1).to_be_bytes());
+
+        let err = decode(&bytes).err().unwrap();
+        assert!(matches!(
+            err,
+            RxcError::IncompatibleVersion {
+                found: 2,
+                expected: 1
+            }
+        ));
+    }
+
+    #[test]
+    fn edited_source_is_detected() {
+        let path = std::env::temp_dir().join(format!("relox-rxc-{}.rx", std::process::id()));
+        std::fs::write(&path, SOURCE).unwrap();
+        let bytes = encode(
+            &compile(SOURCE),
+            "test.rx",
+            &path.display().to_string(),
+            SOURCE,
+        );
+        let compiled = decode(&bytes).unwrap();
+
+        assert!(!compiled.source_changed());
+        std::fs::write(&path, "print 1;").unwrap();
+        assert!(compiled.source_changed());
+        std::fs::remove_file(&path).unwrap();
+    }
+}
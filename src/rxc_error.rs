@@ -0,0 +1,17 @@
+use thiserror::Error;
+
+/// Why a precompiled `.rxc` file can't be loaded
+#[derive(Debug, Clone, Error)]
+pub enum RxcError {
+    #[error("Not a relox bytecode file")]
+    NotBytecode,
+    #[error(
+        "Compiled for bytecode format {found}, this relox runs format {expected}, \
+         recompile it with 'relox compile'"
+    )]
+    IncompatibleVersion { found: u16, expected: u16 },
+    #[error("Bytecode file is truncated")]
+    Truncated,
+    #[error("Bytecode file is corrupt: {0}")]
+    Corrupt(&'static str),
+}
@@ -0,0 +1,60 @@
+/// Byte range `start..end` of a piece of source code
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Span {
+    pub start: usize,
+    pub end: usize,
+}
+
+impl Span {
+    pub fn new(start: usize, end: usize) -> Self {
+        Span { start, end }
+    }
+
+    /// Span covering both `self` and `other` and everything between them
+    pub fn to(self, other: Span) -> Span {
+        Span::new(self.start.min(other.start), self.end.max(other.end))
+    }
+}
+
+/// Maps byte offsets of a source to 1-based line and column numbers
+#[derive(Debug)]
+pub struct SourceMap {
+    /// File name shown in diagnostics
+    pub name: String,
+    /// Byte offset where each line starts
+    line_starts: Vec<usize>,
+    source: String,
+}
+
+impl SourceMap {
+    pub fn new(name: &str, source: &str) -> Self {
+        let line_starts = std::iter::once(0)
+            .chain(source.match_indices('\n').map(|(i, _)| i + 1))
+            .collect();
+
+        SourceMap {
+            name: name.to_string(),
+            line_starts,
+            source: source.to_string(),
+        }
+    }
+
+    /// Text of a 1-based line, without its line break
+    pub fn line_text(&self, line: usize) -> &str {
+        let start = self.line_starts[line - 1];
+        let end = self
+            .line_starts
+            .get(line)
+            .copied()
+            .unwrap_or(self.source.len());
+        self.source[start..end].trim_end_matches(['\n', '\r'])
+    }
+
+    /// Line and column of a byte offset, columns count characters not bytes
+    pub fn line_col(&self, offset: usize) -> (usize, usize) {
+        let offset = offset.min(self.source.len());
+        let line = self.line_starts.partition_point(|&start| start <= offset) - 1;
+        let column = self.source[self.line_starts[line]..offset].chars().count();
+        (line + 1, column + 1)
+    }
+}
@@ -1,9 +1,15 @@
+use crate::span::Span;
 use ecow::EcoString;
+use std::fmt;
 
+#[allow(clippy::upper_case_acronyms)]
 #[derive(Debug, PartialEq, Clone)]
 pub enum TokenType {
     Int(i64),
     Float(f64),
+    Str(EcoString),
+    /// String literal containing `{expression}` segments
+    InterpolatedStr(Vec<StrPart>),
     Identifier(EcoString),
 
     // Operators
@@ -20,6 +26,9 @@ pub enum TokenType {
     GtEq,
     Equal,
     NotEqual,
+    And,
+    Or,
+    Pipe,
 
     // Delimeters
     Semicolon,
@@ -27,22 +36,100 @@ pub enum TokenType {
     LParen,
     RBrace,
     LBrace,
+    RBracket,
+    LBracket,
     Comma,
+    DotDot,
 
     // Keywords
     Let,
+    Const,
     Print,
     If,
     Else,
+    While,
+    For,
+    In,
+    Break,
+    Continue,
     Return,
     Function,
     True,
     False,
+    Nil,
+
+    /// `/// text`, kept for tools, the parser skips it
+    DocComment(EcoString),
 
     EOF,
 }
 
+/// The token as it appears in the source, for error messages.
+/// E.g. `'}'`, `'let'`, `'x'`, `42` or `end of input`
+impl fmt::Display for TokenType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let text = match self {
+            TokenType::Int(value) => return write!(f, "{}", value),
+            TokenType::Float(value) => return write!(f, "{:?}", value),
+            TokenType::Str(value) => return write!(f, "{:?}", value),
+            TokenType::InterpolatedStr(_) => return write!(f, "interpolated string"),
+            TokenType::Identifier(name) => return write!(f, "'{}'", name),
+            TokenType::DocComment(_) => return write!(f, "doc comment"),
+            TokenType::EOF => return write!(f, "end of input"),
+            TokenType::Assign => "=",
+            TokenType::Bang => "!",
+            TokenType::Plus => "+",
+            TokenType::Minus => "-",
+            TokenType::Star => "*",
+            TokenType::Slash => "/",
+            TokenType::Lt => "<",
+            TokenType::Gt => ">",
+            TokenType::LtEq => "<=",
+            TokenType::GtEq => ">=",
+            TokenType::Equal => "==",
+            TokenType::NotEqual => "!=",
+            TokenType::And => "&&",
+            TokenType::Or => "||",
+            TokenType::Pipe => "|",
+            TokenType::Semicolon => ";",
+            TokenType::RParen => ")",
+            TokenType::LParen => "(",
+            TokenType::RBrace => "}",
+            TokenType::LBrace => "{",
+            TokenType::RBracket => "]",
+            TokenType::LBracket => "[",
+            TokenType::Comma => ",",
+            TokenType::DotDot => "..",
+            TokenType::Let => "let",
+            TokenType::Const => "const",
+            TokenType::Print => "print",
+            TokenType::If => "if",
+            TokenType::Else => "else",
+            TokenType::While => "while",
+            TokenType::For => "for",
+            TokenType::In => "in",
+            TokenType::Break => "break",
+            TokenType::Continue => "continue",
+            TokenType::Return => "return",
+            TokenType::Function => "fn",
+            TokenType::True => "true",
+            TokenType::False => "false",
+            TokenType::Nil => "nil",
+        };
+        write!(f, "'{}'", text)
+    }
+}
+
+/// Piece of an interpolated string literal
+#[derive(Debug, PartialEq, Clone)]
+pub enum StrPart {
+    Literal(EcoString),
+    /// Source of an embedded expression and its byte offset, lexed and parsed by the parser
+    Code(EcoString, usize),
+}
+
 #[derive(Debug)]
 pub struct Token {
     pub token: TokenType,
+    pub span: Span,
 }
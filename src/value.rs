@@ -0,0 +1,280 @@
+use crate::environment::ScopeId;
+use crate::parser::FunctionDecl;
+use crate::runtime_error::RuntimeErrorKind;
+use crate::token::TokenType;
+use crate::vm::Closure;
+use ecow::EcoString;
+use std::cmp::Ordering;
+use std::fmt;
+use std::rc::Rc;
+
+/// Runtime value produced by evaluating an expression
+#[derive(Debug, Clone)]
+pub enum Value {
+    Int(i64),
+    Float(f64),
+    Bool(bool),
+    Str(EcoString),
+    Nil,
+    Function(Rc<Function>),
+    Native(NativeFunction),
+    /// Function compiled for the bytecode VM
+    Closure(Rc<Closure>),
+}
+
+/// A function implemented in Rust and exposed to scripts
+#[derive(Clone, Copy)]
+pub struct NativeFunction {
+    pub name: &'static str,
+    pub arity: usize,
+    pub func: fn(&[Value]) -> Result<Value, RuntimeErrorKind>,
+}
+
+impl fmt::Debug for NativeFunction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "<native fn {}>", self.name)
+    }
+}
+
+/// A user-defined function together with the scope it was declared in
+pub struct Function {
+    pub decl: Rc<FunctionDecl>,
+    /// `None` for functions declared at the top level, which only see globals
+    pub closure: Option<ScopeId>,
+}
+
+impl Function {
+    pub fn arity(&self) -> usize {
+        self.decl.params.len()
+    }
+}
+
+// The closure can reach the function again, so only the name is printed
+impl fmt::Debug for Function {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "<fn {}>", self.decl.name)
+    }
+}
+
+impl Value {
+    /// Name of the value's type as shown in error messages
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            Value::Int(_) => "int",
+            Value::Float(_) => "float",
+            Value::Bool(_) => "bool",
+            Value::Str(_) => "string",
+            Value::Nil => "nil",
+            Value::Function(_) | Value::Native(_) | Value::Closure(_) => "function",
+        }
+    }
+
+    /// Scope captured by a function of the tree walker, which the GC has to keep alive
+    pub fn closure(&self) -> Option<ScopeId> {
+        match self {
+            Value::Function(function) => function.closure,
+            _ => None,
+        }
+    }
+
+    /// `nil` and `false` are falsy, every other value is truthy
+    pub fn is_truthy(&self) -> bool {
+        !matches!(self, Value::Nil | Value::Bool(false))
+    }
+
+    pub fn negate(&self) -> Result<Value, RuntimeErrorKind> {
+        match self {
+            Value::Int(value) => value
+                .checked_neg()
+                .map(Value::Int)
+                .ok_or(RuntimeErrorKind::IntegerOverflow),
+            Value::Float(value) => Ok(Value::Float(-value)),
+            _ => Err(RuntimeErrorKind::TypeMismatch {
+                expected: "number",
+                found: self.type_name(),
+            }),
+        }
+    }
+
+    /// Apply an arithmetic operator (`+ - * /`).
+    /// Two ints stay ints, as soon as one side is a float both are promoted to float.
+    pub fn arithmetic(&self, op: &TokenType, other: &Value) -> Result<Value, RuntimeErrorKind> {
+        match (self, other) {
+            (Value::Int(left), Value::Int(right)) => {
+                let result = match op {
+                    TokenType::Plus => left.checked_add(*right),
+                    TokenType::Minus => left.checked_sub(*right),
+                    TokenType::Star => left.checked_mul(*right),
+                    TokenType::Slash => {
+                        if *right == 0 {
+                            return Err(RuntimeErrorKind::DivisionByZero);
+                        }
+                        left.checked_div(*right)
+                    }
+                    _ => return Err(self.invalid_operands(op, other)),
+                };
+                result
+                    .map(Value::Int)
+                    .ok_or(RuntimeErrorKind::IntegerOverflow)
+            }
+
+            (Value::Int(_) | Value::Float(_), Value::Int(_) | Value::Float(_)) => {
+                let (left, right) = (self.as_float(), other.as_float());
+                match op {
+                    TokenType::Plus => Ok(Value::Float(left + right)),
+                    TokenType::Minus => Ok(Value::Float(left - right)),
+                    TokenType::Star => Ok(Value::Float(left * right)),
+                    TokenType::Slash => Ok(Value::Float(left / right)),
+                    _ => Err(self.invalid_operands(op, other)),
+                }
+            }
+
+            (Value::Str(left), Value::Str(right)) if *op == TokenType::Plus => {
+                let mut result = left.clone();
+                result.push_str(right);
+                Ok(Value::Str(result))
+            }
+
+            _ => Err(self.invalid_operands(op, other)),
+        }
+    }
+
+    /// Apply an ordering operator (`< > <= >=`).
+    /// Numbers compare numerically across int and float, strings lexicographically.
+    pub fn compare(&self, op: &TokenType, other: &Value) -> Result<Value, RuntimeErrorKind> {
+        let ordering = match (self, other) {
+            (Value::Int(left), Value::Int(right)) => Some(left.cmp(right)),
+            (Value::Int(_) | Value::Float(_), Value::Int(_) | Value::Float(_)) => {
+                self.as_float().partial_cmp(&other.as_float())
+            }
+            (Value::Str(left), Value::Str(right)) => Some(left.cmp(right)),
+            _ => return Err(self.invalid_operands(op, other)),
+        };
+
+        // NaN is unordered, every comparison with it is false
+        let Some(ordering) = ordering else {
+            return Ok(Value::Bool(false));
+        };
+
+        let result = match op {
+            TokenType::Lt => ordering == Ordering::Less,
+            TokenType::Gt => ordering == Ordering::Greater,
+            TokenType::LtEq => ordering != Ordering::Greater,
+            TokenType::GtEq => ordering != Ordering::Less,
+            _ => return Err(self.invalid_operands(op, other)),
+        };
+        Ok(Value::Bool(result))
+    }
+
+    /// Character at `index` of a string, as a one character string
+    pub fn index(&self, index: &Value) -> Result<Value, RuntimeErrorKind> {
+        let Value::Str(string) = self else {
+            return Err(RuntimeErrorKind::TypeMismatch {
+                expected: "string",
+                found: self.type_name(),
+            });
+        };
+        let Value::Int(index) = index else {
+            return Err(RuntimeErrorKind::TypeMismatch {
+                expected: "int",
+                found: index.type_name(),
+            });
+        };
+
+        usize::try_from(*index)
+            .ok()
+            .and_then(|i| string.chars().nth(i))
+            .map(|c| Value::Str(c.into()))
+            .ok_or_else(|| RuntimeErrorKind::IndexOutOfRange {
+                index: *index,
+                len: string.chars().count(),
+            })
+    }
+
+    /// Characters `start..end` of a string, missing bounds default to the whole string
+    pub fn slice(
+        &self,
+        start: Option<Value>,
+        end: Option<Value>,
+    ) -> Result<Value, RuntimeErrorKind> {
+        let Value::Str(string) = self else {
+            return Err(RuntimeErrorKind::TypeMismatch {
+                expected: "string",
+                found: self.type_name(),
+            });
+        };
+
+        let len = string.chars().count();
+        let bound = |value: Option<Value>, default: usize| match value {
+            None => Ok(default),
+            Some(Value::Int(i)) if (0..=len as i64).contains(&i) => Ok(i as usize),
+            Some(Value::Int(index)) => Err(RuntimeErrorKind::IndexOutOfRange { index, len }),
+            Some(other) => Err(RuntimeErrorKind::TypeMismatch {
+                expected: "int",
+                found: other.type_name(),
+            }),
+        };
+
+        let (start, end) = (bound(start, 0)?, bound(end, len)?);
+        if start > end {
+            return Err(RuntimeErrorKind::InvalidSlice { start, end });
+        }
+
+        Ok(Value::Str(
+            string.chars().skip(start).take(end - start).collect(),
+        ))
+    }
+
+    fn invalid_operands(&self, op: &TokenType, other: &Value) -> RuntimeErrorKind {
+        RuntimeErrorKind::InvalidOperands {
+            op: op.clone(),
+            left: self.type_name(),
+            right: other.type_name(),
+        }
+    }
+
+    /// Numeric promotion, only meaningful for `Int` and `Float`
+    fn as_float(&self) -> f64 {
+        match self {
+            Value::Int(value) => *value as f64,
+            Value::Float(value) => *value,
+            _ => f64::NAN,
+        }
+    }
+}
+
+/// Language equality: ints and floats compare by numeric value,
+/// values of different types are never equal.
+impl PartialEq for Value {
+    fn eq(&self, other: &Value) -> bool {
+        match (self, other) {
+            (Value::Int(left), Value::Int(right)) => left == right,
+            (Value::Int(_) | Value::Float(_), Value::Int(_) | Value::Float(_)) => {
+                self.as_float() == other.as_float()
+            }
+            (Value::Bool(left), Value::Bool(right)) => left == right,
+            (Value::Str(left), Value::Str(right)) => left == right,
+            (Value::Nil, Value::Nil) => true,
+            (Value::Function(left), Value::Function(right)) => Rc::ptr_eq(left, right),
+            (Value::Native(left), Value::Native(right)) => left.name == right.name,
+            (Value::Closure(left), Value::Closure(right)) => Rc::ptr_eq(left, right),
+            _ => false,
+        }
+    }
+}
+
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Value::Int(value) => write!(f, "{}", value),
+            // Debug keeps the trailing ".0" so floats never print like ints
+            Value::Float(value) => write!(f, "{:?}", value),
+            Value::Bool(value) => write!(f, "{}", value),
+            Value::Str(value) => write!(f, "{}", value),
+            Value::Nil => write!(f, "nil"),
+            Value::Function(function) => write!(f, "{:?}", function),
+            Value::Native(function) => write!(f, "{:?}", function),
+            Value::Closure(closure) => write!(f, "{:?}", closure),
+        }
+    }
+}
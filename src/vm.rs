@@ -0,0 +1,494 @@
+use crate::builtins::BUILTINS;
+use crate::chunk::{Constant, FunctionProto, OpCode};
+use crate::environment::MAX_CALL_DEPTH;
+use crate::gc::{GcConfig, GcStats, Handle, Heap, Trace};
+use crate::output;
+use crate::runtime_error::{RuntimeError, RuntimeErrorKind};
+use crate::token::TokenType;
+use crate::value::Value;
+use ecow::EcoString;
+use std::collections::HashMap;
+use std::fmt;
+use std::rc::Rc;
+
+/// A compiled function together with the variables it captured
+pub struct Closure {
+    pub function: Rc<FunctionProto>,
+    pub upvalues: Vec<UpvalueId>,
+}
+
+// Like `Function`, a closure can reach itself through its upvalues
+impl fmt::Debug for Closure {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "<fn {}>", self.function.name)
+    }
+}
+
+/// A captured variable. It points into the stack while the variable's scope is alive,
+/// then holds the value itself so closures outliving the scope still share it.
+#[derive(Debug)]
+pub enum Upvalue {
+    Open(usize),
+    Closed(Value),
+}
+
+/// Handle to an upvalue owned by the VM's `Heap`
+pub type UpvalueId = Handle<Upvalue>;
+
+impl Trace for Upvalue {
+    fn held_by(value: &Value) -> Vec<UpvalueId> {
+        match value {
+            Value::Closure(closure) => closure.upvalues.clone(),
+            _ => Vec::new(),
+        }
+    }
+
+    /// An open upvalue's variable is on the stack, which is a root already
+    fn references(&self) -> Vec<UpvalueId> {
+        match self {
+            Upvalue::Open(_) => Vec::new(),
+            Upvalue::Closed(value) => Self::held_by(value),
+        }
+    }
+}
+
+struct CallFrame {
+    closure: Rc<Closure>,
+    ip: usize,
+    /// Stack index of slot 0, the callee
+    base: usize,
+}
+
+#[derive(Debug)]
+struct Global {
+    value: Value,
+    mutable: bool,
+}
+
+/// Stack based virtual machine running code from the `Compiler`,
+/// with the same observable behavior as the tree walking `Environment`
+pub struct Vm {
+    stack: Vec<Value>,
+    frames: Vec<CallFrame>,
+    globals: HashMap<EcoString, Global>,
+    heap: Heap<Upvalue>,
+    /// Upvalues still pointing into the stack, ordered by slot
+    open_upvalues: Vec<UpvalueId>,
+}
+
+impl Vm {
+    pub fn new(config: GcConfig) -> Self {
+        let globals = BUILTINS
+            .iter()
+            .map(|native| {
+                let global = Global {
+                    value: Value::Native(*native),
+                    mutable: true,
+                };
+                (native.name.into(), global)
+            })
+            .collect();
+
+        Vm {
+            stack: Vec::new(),
+            frames: Vec::new(),
+            globals,
+            heap: Heap::new(config),
+            open_upvalues: Vec::new(),
+        }
+    }
+
+    pub fn gc_stats(&self) -> GcStats {
+        self.heap.stats()
+    }
+
+    /// Run a compiled program. Globals it defines stay around for the next one.
+    pub fn run(&mut self, script: Rc<FunctionProto>) -> Result<(), RuntimeError> {
+        let closure = Rc::new(Closure {
+            function: script,
+            upvalues: Vec::new(),
+        });
+        self.stack.push(Value::Closure(closure.clone()));
+        self.frames.push(CallFrame {
+            closure,
+            ip: 0,
+            base: 0,
+        });
+
+        let result = self.execute();
+        if result.is_err() {
+            self.stack.clear();
+            self.frames.clear();
+            self.open_upvalues.clear();
+        }
+        result
+    }
+
+    fn frame(&mut self) -> &mut CallFrame {
+        self.frames.last_mut().unwrap()
+    }
+
+    fn read_byte(&mut self) -> u8 {
+        let frame = self.frame();
+        let byte = frame.closure.function.chunk.code[frame.ip];
+        frame.ip += 1;
+        byte
+    }
+
+    fn read_u16(&mut self) -> u16 {
+        let frame = self.frame();
+        let value = frame.closure.function.chunk.read_u16(frame.ip);
+        frame.ip += 2;
+        value
+    }
+
+    fn read_constant(&mut self) -> Constant {
+        let index = self.read_u16() as usize;
+        self.frame().closure.function.chunk.constants[index].clone()
+    }
+
+    fn read_name(&mut self) -> EcoString {
+        match self.read_constant() {
+            Constant::Str(name) => name,
+            other => unreachable!("variable name constant is {:?}", other),
+        }
+    }
+
+    fn pop(&mut self) -> Value {
+        self.stack.pop().unwrap()
+    }
+
+    fn peek(&self, distance: usize) -> &Value {
+        &self.stack[self.stack.len() - 1 - distance]
+    }
+
+    fn execute(&mut self) -> Result<(), RuntimeError> {
+        loop {
+            // errors point at the node the failing instruction was compiled from
+            let start = self.frame().ip;
+            let fail = |vm: &mut Vm, kind: RuntimeErrorKind| {
+                let span = vm.frame().closure.function.chunk.span_at(start);
+                RuntimeError::new(kind, span)
+            };
+
+            let byte = self.read_byte();
+            let op = OpCode::try_from(byte)
+                .unwrap_or_else(|byte| panic!("invalid opcode {} at offset {}", byte, start));
+
+            match op {
+                OpCode::Constant => {
+                    let value = match self.read_constant() {
+                        Constant::Int(value) => Value::Int(value),
+                        Constant::Float(value) => Value::Float(value),
+                        Constant::Str(value) => Value::Str(value),
+                        Constant::Function(function) => {
+                            unreachable!("function {} loaded without a closure", function.name)
+                        }
+                    };
+                    self.stack.push(value);
+                }
+                OpCode::Nil => self.stack.push(Value::Nil),
+                OpCode::True => self.stack.push(Value::Bool(true)),
+                OpCode::False => self.stack.push(Value::Bool(false)),
+                OpCode::Pop => {
+                    self.pop();
+                }
+                OpCode::GetLocal => {
+                    let slot = self.read_byte() as usize;
+                    let base = self.frame().base;
+                    self.stack.push(self.stack[base + slot].clone());
+                }
+                OpCode::SetLocal => {
+                    let slot = self.read_byte() as usize;
+                    let base = self.frame().base;
+                    self.stack[base + slot] = self.peek(0).clone();
+                }
+                OpCode::GetUpvalue => {
+                    let index = self.read_byte() as usize;
+                    let upvalue = self.frame().closure.upvalues[index];
+                    let value = match self.heap.get(upvalue) {
+                        Upvalue::Open(slot) => self.stack[*slot].clone(),
+                        Upvalue::Closed(value) => value.clone(),
+                    };
+                    self.stack.push(value);
+                }
+                OpCode::SetUpvalue => {
+                    let index = self.read_byte() as usize;
+                    let upvalue = self.frame().closure.upvalues[index];
+                    let value = self.peek(0).clone();
+                    match self.heap.get_mut(upvalue) {
+                        Upvalue::Open(slot) => self.stack[*slot] = value,
+                        Upvalue::Closed(closed) => *closed = value,
+                    };
+                }
+                OpCode::GetGlobal => {
+                    let name = self.read_name();
+                    match self.globals.get(&name) {
+                        Some(global) => self.stack.push(global.value.clone()),
+                        None => return Err(fail(self, RuntimeErrorKind::UndefinedVariable(name))),
+                    }
+                }
+                OpCode::SetGlobal => {
+                    let name = self.read_name();
+                    let value = self.peek(0).clone();
+                    match self.globals.get_mut(&name) {
+                        Some(Global { mutable: false, .. }) => {
+                            return Err(fail(self, RuntimeErrorKind::AssignToConstant(name)))
+                        }
+                        Some(global) => global.value = value,
+                        None => {
+                            return Err(fail(self, RuntimeErrorKind::UndeclaredAssignment(name)))
+                        }
+                    }
+                }
+                OpCode::AssignConstant => {
+                    let name = self.read_name();
+                    return Err(fail(self, RuntimeErrorKind::AssignToConstant(name)));
+                }
+                OpCode::DefineGlobal | OpCode::DefineConstGlobal => {
+                    let name = self.read_name();
+                    if let Some(Global { mutable: false, .. }) = self.globals.get(&name) {
+                        return Err(fail(self, RuntimeErrorKind::RedeclareConstant(name)));
+                    }
+                    let value = self.pop();
+                    let mutable = op == OpCode::DefineGlobal;
+                    self.globals.insert(name, Global { value, mutable });
+                }
+                OpCode::Equal | OpCode::NotEqual => {
+                    let right = self.pop();
+                    let left = self.pop();
+                    let equal = left == right;
+                    self.stack.push(Value::Bool(if op == OpCode::Equal {
+                        equal
+                    } else {
+                        !equal
+                    }));
+                }
+                OpCode::Less
+                | OpCode::Greater
+                | OpCode::LessEqual
+                | OpCode::GreaterEqual
+                | OpCode::Add
+                | OpCode::Subtract
+                | OpCode::Multiply
+                | OpCode::Divide => {
+                    let right = self.pop();
+                    let left = self.pop();
+                    let result = match op {
+                        OpCode::Less => left.compare(&TokenType::Lt, &right),
+                        OpCode::Greater => left.compare(&TokenType::Gt, &right),
+                        OpCode::LessEqual => left.compare(&TokenType::LtEq, &right),
+                        OpCode::GreaterEqual => left.compare(&TokenType::GtEq, &right),
+                        OpCode::Add => left.arithmetic(&TokenType::Plus, &right),
+                        OpCode::Subtract => left.arithmetic(&TokenType::Minus, &right),
+                        OpCode::Multiply => left.arithmetic(&TokenType::Star, &right),
+                        _ => left.arithmetic(&TokenType::Slash, &right),
+                    };
+                    let value = result.map_err(|kind| fail(self, kind))?;
+                    self.stack.push(value);
+                }
+                OpCode::Negate => {
+                    let value = self.pop().negate().map_err(|kind| fail(self, kind))?;
+                    self.stack.push(value);
+                }
+                OpCode::Not => {
+                    let value = self.pop();
+                    self.stack.push(Value::Bool(!value.is_truthy()));
+                }
+                OpCode::Index => {
+                    let index = self.pop();
+                    let value = self.pop();
+                    let result = value.index(&index).map_err(|kind| fail(self, kind))?;
+                    self.stack.push(result);
+                }
+                OpCode::Slice => {
+                    let flags = self.read_byte();
+                    let end = (flags & 2 != 0).then(|| self.pop());
+                    let start = (flags & 1 != 0).then(|| self.pop());
+                    let value = self.pop();
+                    let result = value.slice(start, end).map_err(|kind| fail(self, kind))?;
+                    self.stack.push(result);
+                }
+                OpCode::BuildString => {
+                    let count = self.read_u16() as usize;
+                    let mut result = EcoString::new();
+                    for part in self.stack.drain(self.stack.len() - count..) {
+                        result.push_str(&part.to_string());
+                    }
+                    self.stack.push(Value::Str(result));
+                }
+                OpCode::CheckInt => {
+                    if !matches!(self.peek(0), Value::Int(_)) {
+                        let kind = RuntimeErrorKind::TypeMismatch {
+                            expected: "int",
+                            found: self.peek(0).type_name(),
+                        };
+                        return Err(fail(self, kind));
+                    }
+                }
+                OpCode::Print => {
+                    let value = self.pop();
+                    output::println(value);
+                }
+                OpCode::Jump => {
+                    let offset = self.read_u16() as usize;
+                    self.frame().ip += offset;
+                }
+                OpCode::JumpIfFalse => {
+                    let offset = self.read_u16() as usize;
+                    if !self.peek(0).is_truthy() {
+                        self.frame().ip += offset;
+                    }
+                }
+                OpCode::Loop => {
+                    let offset = self.read_u16() as usize;
+                    self.frame().ip -= offset;
+                }
+                OpCode::Call => {
+                    let argc = self.read_byte() as usize;
+                    self.call(argc).map_err(|kind| fail(self, kind))?;
+                }
+                OpCode::Closure => {
+                    let Constant::Function(function) = self.read_constant() else {
+                        unreachable!("closure of a constant that isn't a function");
+                    };
+
+                    let mut upvalues = Vec::with_capacity(function.upvalue_count);
+                    for _ in 0..function.upvalue_count {
+                        let is_local = self.read_byte() == 1;
+                        let index = self.read_byte() as usize;
+                        let upvalue = if is_local {
+                            let slot = self.frame().base + index;
+                            self.capture_upvalue(slot)
+                        } else {
+                            self.frame().closure.upvalues[index]
+                        };
+                        upvalues.push(upvalue);
+                    }
+
+                    let closure = Closure { function, upvalues };
+                    self.stack.push(Value::Closure(Rc::new(closure)));
+                }
+                OpCode::CloseUpvalue => {
+                    self.close_upvalues(self.stack.len() - 1);
+                    self.pop();
+                }
+                OpCode::Return => {
+                    let result = self.pop();
+                    let frame = self.frames.pop().unwrap();
+                    self.close_upvalues(frame.base);
+                    self.stack.truncate(frame.base);
+
+                    if self.frames.is_empty() {
+                        return Ok(());
+                    }
+                    self.stack.push(result);
+                }
+            }
+        }
+    }
+
+    /// Call the value below the `argc` arguments on top of the stack
+    fn call(&mut self, argc: usize) -> Result<(), RuntimeErrorKind> {
+        let callee_slot = self.stack.len() - 1 - argc;
+
+        match self.stack[callee_slot].clone() {
+            Value::Closure(closure) => {
+                let function = &closure.function;
+                if argc != function.arity {
+                    return Err(RuntimeErrorKind::ArityMismatch {
+                        name: function.name.clone(),
+                        expected: function.arity,
+                        found: argc,
+                        definition: Some(function.span),
+                    });
+                }
+
+                // the script itself isn't a call
+                if self.frames.len() > MAX_CALL_DEPTH {
+                    return Err(RuntimeErrorKind::StackOverflow(MAX_CALL_DEPTH));
+                }
+
+                self.frames.push(CallFrame {
+                    closure,
+                    ip: 0,
+                    base: callee_slot,
+                });
+            }
+            Value::Native(native) => {
+                if argc != native.arity {
+                    return Err(RuntimeErrorKind::ArityMismatch {
+                        name: native.name.into(),
+                        expected: native.arity,
+                        found: argc,
+                        definition: None,
+                    });
+                }
+
+                let result = (native.func)(&self.stack[callee_slot + 1..])?;
+                self.stack.truncate(callee_slot);
+                self.stack.push(result);
+            }
+            other => {
+                return Err(RuntimeErrorKind::TypeMismatch {
+                    expected: "function",
+                    found: other.type_name(),
+                })
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Stack slot of an upvalue still in `open_upvalues`
+    fn open_slot(&self, upvalue: UpvalueId) -> usize {
+        match self.heap.get(upvalue) {
+            Upvalue::Open(slot) => *slot,
+            Upvalue::Closed(_) => unreachable!("closed upvalue left in the open list"),
+        }
+    }
+
+    /// Upvalue for the local in stack `slot`, shared with closures that captured it before
+    fn capture_upvalue(&mut self, slot: usize) -> UpvalueId {
+        let position = self
+            .open_upvalues
+            .partition_point(|&upvalue| self.open_slot(upvalue) < slot);
+
+        if let Some(&existing) = self.open_upvalues.get(position) {
+            if self.open_slot(existing) == slot {
+                return existing;
+            }
+        }
+
+        if self.heap.should_collect() {
+            self.collect_garbage();
+        }
+        let upvalue = self.heap.alloc(Upvalue::Open(slot));
+        self.open_upvalues.insert(position, upvalue);
+        upvalue
+    }
+
+    /// Free the upvalues no value on the stack, global or running closure can reach.
+    /// Upvalues captured earlier by the closure being created are open or belong to
+    /// the running closure, so they are rooted too.
+    fn collect_garbage(&mut self) {
+        let frames = self.frames.iter().flat_map(|frame| &frame.closure.upvalues);
+        let globals = self.globals.values().map(|global| &global.value);
+        self.heap.collect(
+            self.open_upvalues.iter().chain(frames).copied(),
+            self.stack.iter().chain(globals),
+        );
+    }
+
+    /// Move the locals from stack slot `from` upward into the upvalues capturing them
+    fn close_upvalues(&mut self, from: usize) {
+        let position = self
+            .open_upvalues
+            .partition_point(|&upvalue| self.open_slot(upvalue) < from);
+
+        for upvalue in self.open_upvalues.split_off(position) {
+            let slot = self.open_slot(upvalue);
+            *self.heap.get_mut(upvalue) = Upvalue::Closed(self.stack[slot].clone());
+        }
+    }
+}
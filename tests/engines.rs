@@ -0,0 +1,102 @@
+use std::process::{Command, Output};
+
+/// Scripts that must behave the same on the tree walker and on the VM
+const SCRIPTS: &[(&str, &str)] = &[
+    (
+        "closures",
+        "fn make_counter() { let n = 0; return fn() { n = n + 1; return n; }; }
+         let c = make_counter(); print c(); print c();
+         let a = nil; for i in 0..3 { if i == 1 { a = || i; } } print a();",
+    ),
+    (
+        "control flow",
+        "let total = 0;
+         for i in 0..5 { for j in 0..5 { if j == 3 { break; } if j == 1 { continue; } total = total + i * j; } }
+         let k = 0; while k < 3 { k = k + 1; }
+         print total; print k; print 1 < 2 && 3 > 4 || true;",
+    ),
+    (
+        "functions and strings",
+        "fn fib(n) { if n < 2 { return n; } return fib(n - 1) + fib(n - 2); }
+         let s = \"hello\";
+         print fib(15); print \"{s} world {1 + 2}\"; print s[1..3]; print len(s); print 7 / 2;",
+    ),
+    (
+        "recursive lambda",
+        "fn main() { let fact = fn(n) { if n < 2 { return 1; } return n * fact(n - 1); }; print fact(5); }
+         main();",
+    ),
+    ("lambda returning itself", "{ let x = fn() { return x; }; print x(); }"),
+    (
+        "recursive local function",
+        "{ fn count(n) { if n > 0 { return count(n - 1) + 1; } return 0; } print count(10); }",
+    ),
+    ("runtime error", "print \"start\"; print 1 / 0; print \"unreachable\";"),
+    ("local constant", "print \"start\"; { const c = 1; c = 2; }"),
+    ("constant in an uncalled function", "fn f() { const c = 1; c = 2; } print \"ok\";"),
+    ("captured constant", "fn f() { const c = 1; return fn() { c = 2; }; } print \"ok\";"),
+    ("global constant", "print \"start\"; const g = 1; g = 2; print \"unreachable\";"),
+];
+
+fn run(engine: &[&str], code: &str) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_relox"))
+        .args(engine)
+        .args(["-e", code])
+        .output()
+        .expect("relox should start")
+}
+
+#[test]
+fn engines_agree() {
+    for (name, code) in SCRIPTS {
+        let tree_walker = run(&[], code);
+        let vm = run(&["--vm"], code);
+
+        assert_eq!(
+            String::from_utf8_lossy(&tree_walker.stdout),
+            String::from_utf8_lossy(&vm.stdout),
+            "stdout of '{}'",
+            name
+        );
+        assert_eq!(
+            tree_walker.status.code(),
+            vm.status.code(),
+            "exit code of '{}'",
+            name
+        );
+    }
+}
+
+#[test]
+fn assigning_a_local_constant_fails_when_it_runs() {
+    for engine in [&[][..], &["--vm"]] {
+        let output = run(engine, "print \"start\"; { const c = 1; c = 2; }");
+        assert_eq!(String::from_utf8_lossy(&output.stdout), "start\n");
+        assert_eq!(output.status.code(), Some(70));
+    }
+}
+
+#[test]
+fn collecting_under_stress_keeps_the_output() {
+    let code = "fn make_counter() { let n = 0; return fn() { n = n + 1; return n; }; }
+                let counters = 0;
+                for i in 0..200 {
+                    let c = make_counter(); c(); counters = counters + c();
+                    let f = nil; f = fn(k) { if k > 0 { return f(k - 1); } return i; };
+                    if i < 3 { print f(3); }
+                }
+                print counters;";
+
+    for engine in [&[][..], &["--vm"]] {
+        let normal = run(engine, code);
+        let stressed = run(&[engine, &["--gc-stress"]].concat(), code);
+
+        assert_eq!(String::from_utf8_lossy(&normal.stdout), "0\n1\n2\n400\n");
+        assert_eq!(
+            String::from_utf8_lossy(&normal.stdout),
+            String::from_utf8_lossy(&stressed.stdout)
+        );
+        assert_eq!(normal.status.code(), Some(0));
+        assert_eq!(stressed.status.code(), Some(0));
+    }
+}